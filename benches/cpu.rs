@@ -0,0 +1,126 @@
+// Instructions per second of the CPU core for a few representative workloads.
+// Run with `cargo bench`, the ROMs are loaded from rom/ like the emulator does.
+
+#[macro_use]
+extern crate criterion;
+extern crate emu8080;
+
+use criterion::{Criterion, Benchmark, Throughput};
+use emu8080::machine::Machine;
+
+// instructions run per iteration by the synthetic workloads
+const STEPS: u64 = 100_000;
+// one second of Invaders
+const FRAMES: u64 = 60;
+
+// ADD, ADC, SUB, SBB, logic, DAA, INR/DCR and a compare in a tight loop
+const ARITHMETIC: &[u8] = &[
+	0x31, 0x00, 0x24,	// LXI SP,2400
+	0x06, 0x00,		// MVI B,00
+	0x0E, 0x37,		// MVI C,37
+	0x16, 0x1F,		// MVI D,1F
+	0x80,			// 0009: ADD B
+	0x89,			// ADC C
+	0x92,			// SUB D
+	0x9B,			// SBB E
+	0xA1,			// ANA C
+	0xB0,			// ORA B
+	0xA8,			// XRA B
+	0x27,			// DAA
+	0x04,			// INR B
+	0x0D,			// DCR C
+	0xFE, 0x80,		// CPI 80
+	0xC3, 0x09, 0x00	// JMP 0009
+];
+
+// PUSH/POP of all pairs around nested CALLs
+const STACK: &[(u16, &[u8])] = &[
+	(0x0000, &[
+		0x31, 0x00, 0x24,	// LXI SP,2400
+		0xC5,			// 0003: PUSH B
+		0xD5,			// PUSH D
+		0xE5,			// PUSH H
+		0xF5,			// PUSH PSW
+		0xCD, 0x20, 0x00,	// CALL 0020
+		0xF1,			// POP PSW
+		0xE1,			// POP H
+		0xD1,			// POP D
+		0xC1,			// POP B
+		0xC3, 0x03, 0x00	// JMP 0003
+	]),
+	(0x0020, &[
+		0xCD, 0x28, 0x00,	// CALL 0028
+		0xE3,			// XTHL
+		0xE3,			// XTHL
+		0xC9			// RET
+	]),
+	(0x0028, &[
+		0xC5,			// PUSH B
+		0xC1,			// POP B
+		0xC9			// RET
+	])
+];
+
+fn machine(parts: &[(u16, &[u8])]) -> Machine {
+	let mut m = Machine::new();
+	// the first part is loaded last, so execution starts there
+	for &(offset, code) in parts.iter().rev() {
+		m.load_program(code, offset as usize).unwrap();
+	}
+	m
+}
+
+fn read_rom(filename: &str) -> Vec<u8> {
+	std::fs::read(filename).expect("ROM missing")
+}
+
+fn run_steps(m: &mut Machine) {
+	for _ in 0..STEPS {
+		m.step();
+	}
+	assert!(!m.is_halted());
+}
+
+fn cpudiag(c: &mut Criterion) {
+	// a warm boot restarts the diagnostics and BDOS calls return right away
+	let rom = read_rom("rom/cpudiag.bin");
+	let mut m = machine(&[(0x0100, &rom), (0x0005, &[0xC9]), (0x0000, &[0xC3, 0x00, 0x01])]);
+	c.bench("cpu", Benchmark::new("cpudiag", move |b| b.iter(|| run_steps(&mut m)))
+		.throughput(Throughput::Elements(STEPS as u32)));
+}
+
+fn invaders(c: &mut Criterion) {
+	// attract mode is deterministic, so every run executes the same instructions
+	let rom = read_rom("rom/invaders.bin");
+	let mut m = machine(&[(0x0000, &rom)]);
+	for _ in 0..FRAMES {
+		m.run_frame();
+	}
+	let instructions = m.instructions();
+
+	c.bench("cpu", Benchmark::new(format!("invaders {} frames", FRAMES), move |b| b.iter_with_setup(
+		|| machine(&[(0x0000, &rom)]),
+		|mut m| {
+			for _ in 0..FRAMES {
+				m.run_frame();
+			}
+			m
+		}))
+		.sample_size(20)
+		.throughput(Throughput::Elements(instructions as u32)));
+}
+
+fn arithmetic(c: &mut Criterion) {
+	let mut m = machine(&[(0x0000, ARITHMETIC)]);
+	c.bench("cpu", Benchmark::new("arithmetic", move |b| b.iter(|| run_steps(&mut m)))
+		.throughput(Throughput::Elements(STEPS as u32)));
+}
+
+fn stack(c: &mut Criterion) {
+	let mut m = machine(STACK);
+	c.bench("cpu", Benchmark::new("stack", move |b| b.iter(|| run_steps(&mut m)))
+		.throughput(Throughput::Elements(STEPS as u32)));
+}
+
+criterion_group!(benches, cpudiag, invaders, arithmetic, stack);
+criterion_main!(benches);
@@ -1,7 +1,24 @@
-#[derive(Debug)]
+use std::fmt;
+
+use cpu::{FLAG_AC, FLAG_CY};
+
+lazy_static! {
+	// Carry and auxiliary carry indexed by carry << 16 | x << 8 | y, built on first use
+	// from the plain arithmetic in `add_reference` and `sub_reference`.
+	static ref ADD_FLAGS: Vec<u8> = flag_table(add_reference);
+	static ref SUB_FLAGS: Vec<u8> = flag_table(sub_reference);
+}
+
+fn flag_table(reference: fn(u8, u8, u8) -> (u8, bool, bool)) -> Vec<u8> {
+	(0..0x20000).map(|i| pack(reference((i >> 8) as u8, i as u8, (i >> 16) as u8))).collect()
+}
+
 pub struct ALU {
 	pub cy: bool,
 	pub ac: bool,
+	// the shared tables, so lookups don't go through the lazy initialisation
+	add_flags: &'static [u8],
+	sub_flags: &'static [u8]
 }
 
 impl ALU {
@@ -9,67 +26,229 @@ impl ALU {
 		ALU {
 			cy: false,
 			ac: false,
+			add_flags: &ADD_FLAGS,
+			sub_flags: &SUB_FLAGS
 		}
 	}
 
 	pub fn add(&mut self, x: u8, y: u8) -> u8 {
-		let res = (x as i32) + (y as i32);
-		let ac = ((x & 0x0F) as i32) + ((y & 0x0F) as i32);
-		self.check_ac(ac);
-		self.check_carry(res);
-		res as u8
+		self.add3(x, y, 0)
 	}
 
+	// z is the carry, 0 or 1
 	pub fn add3(&mut self, x: u8, y: u8, z: u8) -> u8 {
-		let res = (x as i32) + (y as i32) + (z as i32);
-		let ac = ((x & 0x0F) as i32) + ((y & 0x0F) as i32) + ((z & 0x0F) as i32);
-		self.check_ac(ac);
-		self.check_carry(res);
-		res as u8
+		let flags = self.add_flags[index(x, y, z)];
+		self.unpack(flags);
+		x.wrapping_add(y).wrapping_add(z & 1)
 	}
 
 	pub fn addx(&mut self, x: u16, y: u16) -> u16 {
-		let res = (x as i32) + (y as i32);
-		self.check_carry16(res);
-		res as u16
+		let (res, cy) = x.overflowing_add(y);
+		self.cy = cy;
+		res
 	}
 
 	pub fn sub(&mut self, x: u8, y: u8) -> u8 {
-		let res = (x as i32) - (y as i32);
-		let ac = ((x & 0x0F) as i32) - ((y & 0x0F) as i32);
-		self.check_ac(ac);
-		self.check_carry(res);
-		res as u8
+		self.sub3(x, y, 0)
 	}
 
+	// z is the borrow, 0 or 1
 	pub fn sub3(&mut self, x: u8, y: u8, z: u8) -> u8 {
-		let res = (x as i32) - (y as i32) - (z as i32);
-		let ac = ((x & 0x0F) as i32) - ((y & 0x0F) as i32) - ((z & 0x0F) as i32);
-		self.check_ac(ac);
-		self.check_carry(res);
-		res as u8
+		let flags = self.sub_flags[index(x, y, z)];
+		self.unpack(flags);
+		x.wrapping_sub(y).wrapping_sub(z & 1)
 	}
 
 	pub fn subx(&mut self, x: u16, y: u16) -> u16 {
-		let res = (x as i32) - (y as i32);
-		self.check_carry16(res);
-		res as u16
+		let (res, cy) = x.overflowing_sub(y);
+		self.cy = cy;
+		res
+	}
+
+	// Decimal adjust of the accumulator, carry and auxiliary carry are the flags before.
+	// The carry stays set once it was, it isn't cleared by the adjust.
+	pub fn daa(&mut self, a: u8, cy: bool, ac: bool) -> u8 {
+		let mut correction = 0;
+		if (a & 0x0F) > 9 || ac {
+			correction |= 0x06;
+		}
+		if a > 0x99 || cy {
+			correction |= 0x60;
+		}
+		let res = self.add(a, correction);
+		self.cy |= cy;
+		res
+	}
+
+	fn unpack(&mut self, flags: u8) {
+		self.cy = flags & FLAG_CY != 0;
+		self.ac = flags & FLAG_AC != 0;
+	}
+}
+
+impl fmt::Debug for ALU {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "ALU {{ cy: {}, ac: {} }}", self.cy, self.ac)
+	}
+}
+
+fn index(x: u8, y: u8, carry: u8) -> usize {
+	((carry as usize & 1) << 16) | ((x as usize) << 8) | y as usize
+}
+
+fn pack((_, cy, ac): (u8, bool, bool)) -> u8 {
+	(if cy { FLAG_CY } else { 0 }) | (if ac { FLAG_AC } else { 0 })
+}
+
+// x + y + carry: result, carry out of bit 7, carry out of bit 3
+pub fn add_reference(x: u8, y: u8, carry: u8) -> (u8, bool, bool) {
+	let res = (x as i32) + (y as i32) + (carry as i32);
+	let ac = ((x & 0x0F) as i32) + ((y & 0x0F) as i32) + (carry as i32);
+	(res as u8, res > 0xFF, ac > 0xF)
+}
+
+// x - y - borrow: result, borrow into bit 7, auxiliary carry
+// The 8080 adds the complement of y, the auxiliary carry is the carry out of bit 3 of that sum.
+pub fn sub_reference(x: u8, y: u8, borrow: u8) -> (u8, bool, bool) {
+	let res = (x as i32) - (y as i32) - (borrow as i32);
+	let ac = ((x & 0x0F) as i32) + ((!y & 0x0F) as i32) + (1 - (borrow & 1) as i32);
+	(res as u8, res < 0, ac > 0xF)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A ripple carry adder, one bit at a time like the 8080 does it.
+	// Returns the sum and the carry out of every bit.
+	fn adder(x: u16, y: u16, carry: bool, bits: u32) -> (u16, u32) {
+		let (mut sum, mut carries) = (0, 0);
+		let mut c = carry as u16;
+		for i in 0..bits {
+			let (a, b) = ((x >> i) & 1, (y >> i) & 1);
+			sum |= (a ^ b ^ c) << i;
+			c = (a & b) | (a & c) | (b & c);
+			carries |= (c as u32) << i;
+		}
+		(sum, carries)
 	}
 
+	// result, carry and auxiliary carry of x + y + carry
+	fn model_add(x: u8, y: u8, carry: bool) -> (u8, bool, bool) {
+		let (sum, carries) = adder(x as u16, y as u16, carry, 8);
+		(sum as u8, carries & 0x80 != 0, carries & 0x08 != 0)
+	}
 
-	fn check_carry(&mut self, res: i32) {
-		let min = u8::min_value() as i32;
-		let max = u8::max_value() as i32;
-		self.cy = res < min || res > max;
+	// x - y - borrow is x + !y + !borrow, the carry out is inverted to a borrow
+	// while the auxiliary carry is taken as it comes out of the adder
+	fn model_sub(x: u8, y: u8, borrow: bool) -> (u8, bool, bool) {
+		let (sum, carries) = adder(x as u16, !y as u16 & 0xFF, !borrow, 8);
+		(sum as u8, carries & 0x80 == 0, carries & 0x08 != 0)
 	}
 
-	fn check_carry16(&mut self, res: i32) {
-		let min = u16::min_value() as i32;
-		let max = u16::max_value() as i32;
-		self.cy = res < min || res > max;
+	// DAA as described in the 8080 manual: result, carry and auxiliary carry
+	fn model_daa(a: u8, cy: bool, ac: bool) -> (u8, bool, bool) {
+		let mut res = a;
+		let mut aux = false;
+		let mut carry = cy;
+		if (a & 0x0F) > 9 || ac {
+			let (sum, carries) = adder(res as u16, 0x06, false, 8);
+			res = sum as u8;
+			aux = carries & 0x08 != 0;
+			// the first step can carry out as well
+			carry |= carries & 0x80 != 0;
+		}
+		if (res >> 4) > 9 || carry {
+			let (sum, carries) = adder(res as u16, 0x60, false, 8);
+			res = sum as u8;
+			carry |= carries & 0x80 != 0;
+		}
+		(res, carry, aux)
+	}
+
+	#[test]
+	fn add_every_pair() {
+		let mut alu = ALU::new();
+		for x in 0..=255u8 {
+			for y in 0..=255u8 {
+				let res = alu.add(x, y);
+				assert_eq!((res, alu.cy, alu.ac), model_add(x, y, false), "{:02X} + {:02X}", x, y);
+				for &carry in [false, true].iter() {
+					let res = alu.add3(x, y, carry as u8);
+					assert_eq!((res, alu.cy, alu.ac), model_add(x, y, carry), "{:02X} + {:02X} + {}", x, y, carry);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn sub_every_pair() {
+		let mut alu = ALU::new();
+		for x in 0..=255u8 {
+			for y in 0..=255u8 {
+				let res = alu.sub(x, y);
+				assert_eq!((res, alu.cy, alu.ac), model_sub(x, y, false), "{:02X} - {:02X}", x, y);
+				for &borrow in [false, true].iter() {
+					let res = alu.sub3(x, y, borrow as u8);
+					assert_eq!((res, alu.cy, alu.ac), model_sub(x, y, borrow), "{:02X} - {:02X} - {}", x, y, borrow);
+				}
+			}
+		}
 	}
 
-	fn check_ac(&mut self, val: i32) {
-		self.ac = val > 0xF;
+	#[test]
+	fn references_match_model() {
+		for x in 0..=255u8 {
+			for y in 0..=255u8 {
+				for &c in [false, true].iter() {
+					assert_eq!(add_reference(x, y, c as u8), model_add(x, y, c), "{:02X} + {:02X} + {}", x, y, c);
+					assert_eq!(sub_reference(x, y, c as u8), model_sub(x, y, c), "{:02X} - {:02X} - {}", x, y, c);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn daa_every_state() {
+		let mut alu = ALU::new();
+		for a in 0..=255u8 {
+			for &cy in [false, true].iter() {
+				for &ac in [false, true].iter() {
+					let res = alu.daa(a, cy, ac);
+					assert_eq!((res, alu.cy, alu.ac), model_daa(a, cy, ac), "DAA of {:02X}, cy {}, ac {}", a, cy, ac);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn daa_adjusts_bcd_sums() {
+		// adding two BCD numbers and adjusting gives their decimal sum
+		let mut alu = ALU::new();
+		for x in 0..100u8 {
+			for y in 0..100u8 {
+				let bcd = |n: u8| (n / 10) << 4 | (n % 10);
+				let sum = alu.add(bcd(x), bcd(y));
+				let res = alu.daa(sum, alu.cy, alu.ac);
+				assert_eq!((res, alu.cy), (bcd((x + y) % 100), x + y >= 100), "{} + {}", x, y);
+			}
+		}
+	}
+
+	quickcheck! {
+		fn addx_matches_model(x: u16, y: u16) -> bool {
+			let mut alu = ALU::new();
+			let res = alu.addx(x, y);
+			let (sum, carries) = adder(x, y, false, 16);
+			res == sum && alu.cy == (carries & 0x8000 != 0)
+		}
+
+		fn subx_matches_model(x: u16, y: u16) -> bool {
+			let mut alu = ALU::new();
+			let res = alu.subx(x, y);
+			let (sum, carries) = adder(x, !y, true, 16);
+			res == sum && alu.cy == (carries & 0x8000 == 0)
+		}
 	}
 }
@@ -0,0 +1,179 @@
+use image;
+use image::{RgbaImage, Rgba};
+use std::path::Path;
+use toml;
+
+use {WIDTH, HEIGHT};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtworkConfig {
+	// shows through where the screen is dark, like the moon behind the monitor
+	pub backdrop: Option<String>,
+	// drawn on top, transparent where the screen shows
+	pub bezel: Option<String>,
+	// [x, y, w, h] of the screen inside the artwork, centred if not given
+	pub screen: Option<[u32; 4]>
+}
+
+impl ArtworkConfig {
+	// picks up artwork/<profile>/backdrop.png and bezel.png if present
+	pub fn for_profile(name: &str) -> ArtworkConfig {
+		let find = |file: &str| {
+			let path = format!("artwork/{}/{}", name, file);
+			if Path::new(&path).exists() { Some(path) } else { None }
+		};
+
+		ArtworkConfig {
+			backdrop: find("backdrop.png"),
+			bezel: find("bezel.png"),
+			screen: None
+		}
+	}
+
+	pub fn from_table(table: &toml::value::Table, base: &ArtworkConfig) -> ArtworkConfig {
+		let mut config = base.clone();
+
+		if let Some(file) = table.get("backdrop").and_then(|v| v.as_str()) {
+			config.backdrop = if file.is_empty() { None } else { Some(file.to_string()) };
+		}
+		if let Some(file) = table.get("bezel").and_then(|v| v.as_str()) {
+			config.bezel = if file.is_empty() { None } else { Some(file.to_string()) };
+		}
+		if let Some(rect) = table.get("screen").and_then(|v| v.as_array()) {
+			let values: Vec<u32> = rect.iter().filter_map(|v| v.as_integer()).map(|v| v.max(0) as u32).collect();
+			if values.len() == 4 {
+				config.screen = Some([values[0], values[1], values[2], values[3]]);
+			}
+			else {
+				println!("=> Ignoring invalid artwork screen rect, expected [x, y, w, h]");
+			}
+		}
+		config
+	}
+
+	pub fn to_table(&self) -> toml::value::Table {
+		let mut table = toml::value::Table::new();
+		table.insert("backdrop".to_string(), toml::Value::String(self.backdrop.clone().unwrap_or_default()));
+		table.insert("bezel".to_string(), toml::Value::String(self.bezel.clone().unwrap_or_default()));
+		if let Some(rect) = self.screen {
+			table.insert("screen".to_string(), toml::Value::Array(rect.iter().map(|&v| toml::Value::Integer(v as i64)).collect()));
+		}
+		table
+	}
+}
+
+fn load(file: &Option<String>) -> Option<RgbaImage> {
+	match *file {
+		Some(ref file) => match image::open(file) {
+			Ok(img) => Some(img.to_rgba()),
+			Err(e) => {
+				println!("=> Can't load artwork {}: {}", file, e);
+				None
+			}
+		},
+		None => None
+	}
+}
+
+
+// Composites the screen additively over the backdrop and puts the bezel on top.
+#[derive(Debug)]
+pub struct Cabinet {
+	// backdrop scaled to the output, used below the screen
+	backdrop: RgbaImage,
+	bezel: Option<RgbaImage>,
+	screen: [u32; 4],
+	output: RgbaImage,
+	enabled: bool
+}
+
+impl Cabinet {
+	pub fn new(config: &ArtworkConfig) -> Cabinet {
+		let backdrop = load(&config.backdrop);
+		let bezel = load(&config.bezel);
+
+		let (w, h) = match (&bezel, &backdrop) {
+			(&Some(ref img), _) | (&None, &Some(ref img)) => img.dimensions(),
+			_ => (WIDTH, HEIGHT)
+		};
+		let enabled = backdrop.is_some() || bezel.is_some();
+
+		let backdrop = match backdrop {
+			Some(img) => image::imageops::resize(&img, w, h, image::FilterType::Triangle),
+			None => RgbaImage::from_pixel(w, h, Rgba([0, 0, 0, 255]))
+		};
+
+		let mut screen = config.screen.unwrap_or_else(|| {
+			// largest centred area with the aspect ratio of the screen
+			let sw = (h * WIDTH / HEIGHT).min(w);
+			let sh = sw * HEIGHT / WIDTH;
+			[(w - sw) / 2, (h - sh) / 2, sw, sh]
+		});
+		screen[2] = screen[2].max(1);
+		screen[3] = screen[3].max(1);
+
+		// everything outside the screen never changes
+		let mut output = backdrop.clone();
+		if let Some(ref bezel) = bezel {
+			for (x, y, pixel) in output.enumerate_pixels_mut() {
+				*pixel = blend(*pixel, *bezel.get_pixel(x, y));
+			}
+		}
+
+		Cabinet {
+			backdrop,
+			bezel,
+			screen,
+			output,
+			enabled
+		}
+	}
+
+	pub fn size(&self) -> (u32, u32) {
+		self.output.dimensions()
+	}
+
+	pub fn compose<'a>(&'a mut self, screen: &'a RgbaImage) -> &'a RgbaImage {
+		if !self.enabled {
+			return screen;
+		}
+
+		let (w, h) = self.output.dimensions();
+		// the screen may be larger than the emulated one after filtering
+		let (src_w, src_h) = screen.dimensions();
+		let [sx, sy, sw, sh] = self.screen;
+		for y in sy..(sy + sh).min(h) {
+			let src_y = (y - sy) * src_h / sh;
+			for x in sx..(sx + sw).min(w) {
+				let src = screen.get_pixel((x - sx) * src_w / sw, src_y);
+				let mut pixel = add(*self.backdrop.get_pixel(x, y), *src);
+				if let Some(ref bezel) = self.bezel {
+					pixel = blend(pixel, *bezel.get_pixel(x, y));
+				}
+				self.output.put_pixel(x, y, pixel);
+			}
+		}
+		&self.output
+	}
+}
+
+fn add(a: Rgba<u8>, b: Rgba<u8>) -> Rgba<u8> {
+	Rgba([
+		a.data[0].saturating_add(b.data[0]),
+		a.data[1].saturating_add(b.data[1]),
+		a.data[2].saturating_add(b.data[2]),
+		255
+	])
+}
+
+// b over a using the alpha of b
+fn blend(a: Rgba<u8>, b: Rgba<u8>) -> Rgba<u8> {
+	let alpha = b.data[3] as u32;
+	let mix = |x: u8, y: u8| ((x as u32 * (255 - alpha) + y as u32 * alpha) / 255) as u8;
+	Rgba([
+		mix(a.data[0], b.data[0]),
+		mix(a.data[1], b.data[1]),
+		mix(a.data[2], b.data[2]),
+		255
+	])
+}
@@ -0,0 +1,350 @@
+#[cfg(feature = "audio")]
+extern crate ears;
+#[cfg(feature = "rust-audio")]
+extern crate cpal;
+
+#[cfg(feature = "audio")]
+use self::ears::{AudioController, Sound};
+#[cfg(feature = "rust-audio")]
+use self::cpal::{StreamData, UnknownTypeOutputBuffer};
+#[cfg(feature = "rust-audio")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "rust-audio")]
+use std::thread;
+
+#[cfg(feature = "audio")]
+use std::env;
+
+use std::fmt;
+use std::path::Path;
+
+use synth;
+use sound::*;
+use machine::CPU_HZ;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioBackend {
+	Rust,
+	Ears,
+	Null,
+	File(String)
+}
+
+impl Default for AudioBackend {
+	fn default() -> AudioBackend {
+		if cfg!(feature = "rust-audio") {
+			AudioBackend::Rust
+		}
+		else if cfg!(feature = "audio") {
+			AudioBackend::Ears
+		}
+		else {
+			AudioBackend::Null
+		}
+	}
+}
+
+impl AudioBackend {
+	pub fn name(&self) -> &str {
+		match *self {
+			AudioBackend::Rust => "rust",
+			AudioBackend::Ears => "ears",
+			AudioBackend::Null => "null",
+			AudioBackend::File(_) => "file"
+		}
+	}
+}
+
+// Receives the decoded sound changes, see `SoundPorts`.
+// `start` plays a sound from the beginning, `stop` silences it immediately.
+pub trait AudioSink: fmt::Debug {
+	fn start(&mut self, sound: usize, cycle: u64);
+	fn stop(&mut self, sound: usize, cycle: u64);
+}
+
+pub fn create_sink(backend: &AudioBackend) -> Box<dyn AudioSink> {
+	match *backend {
+		AudioBackend::Rust => rust_sink(),
+		AudioBackend::Ears => ears_sink(),
+		AudioBackend::Null => Box::new(NullSink),
+		AudioBackend::File(ref filename) => Box::new(FileSink::new(filename))
+	}
+}
+
+fn sound_file(i: usize) -> String {
+	format!("sound/{}.wav", i)
+}
+
+// sample file if present, synthesized sound otherwise
+fn load_sounds() -> Vec<(Vec<i16>, u32)> {
+	(0..synth::SOUND_COUNT).map(|i| {
+		let file = sound_file(i);
+		if Path::new(&file).exists() {
+			match synth::read_wav(&file) {
+				Ok(sound) => return sound,
+				Err(e) => println!("=> Can't load sound file: {}", e)
+			}
+		}
+		(synth::sound(i), synth::SAMPLE_RATE)
+	}).collect()
+}
+
+
+#[derive(Debug)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+	fn start(&mut self, _sound: usize, _cycle: u64) {}
+	fn stop(&mut self, _sound: usize, _cycle: u64) {}
+}
+
+
+// Collects the sound changes and renders them to a WAV file when dropped.
+#[derive(Debug)]
+pub struct FileSink {
+	filename: String,
+	timeline: SoundTimeline
+}
+
+impl FileSink {
+	pub fn new(filename: &str) -> FileSink {
+		FileSink {
+			filename: filename.to_string(),
+			timeline: SoundTimeline::new()
+		}
+	}
+}
+
+impl AudioSink for FileSink {
+	fn start(&mut self, sound: usize, cycle: u64) {
+		self.timeline.push(SoundEvent {cycle, sound, on: true});
+	}
+
+	fn stop(&mut self, sound: usize, cycle: u64) {
+		self.timeline.push(SoundEvent {cycle, sound, on: false});
+	}
+}
+
+impl Drop for FileSink {
+	fn drop(&mut self) {
+		let events = self.timeline.events();
+		// one second of tail so the last one-shots can finish
+		let end = events.last().map(|e| e.cycle).unwrap_or(0) + CPU_HZ;
+		match synth::write_wav(&self.filename, &render(events, end, CPU_HZ)) {
+			Ok(_) => println!("=> Audio written to {}", self.filename),
+			Err(e) => println!("=> Can't write audio to {}: {}", self.filename, e)
+		}
+	}
+}
+
+
+// Software mixer used by the pure Rust backend.
+pub struct Mixer {
+	sounds: Vec<(Vec<i16>, u32)>,
+	// playback position per sound, in samples of that sound
+	voices: Vec<Option<f64>>,
+	rate: u32
+}
+
+impl Mixer {
+	pub fn new(rate: u32) -> Mixer {
+		Mixer {
+			sounds: load_sounds(),
+			voices: vec![None; synth::SOUND_COUNT],
+			rate
+		}
+	}
+
+	pub fn start(&mut self, sound: usize) {
+		self.voices[sound] = Some(0.0);
+	}
+
+	pub fn stop(&mut self, sound: usize) {
+		self.voices[sound] = None;
+	}
+
+	pub fn next(&mut self) -> f32 {
+		let mut mix = 0.0;
+		for (i, voice) in self.voices.iter_mut().enumerate() {
+			let (ref samples, rate) = self.sounds[i];
+			if let Some(pos) = *voice {
+				let mut n = pos as usize;
+				if n >= samples.len() && is_looping(i) && !samples.is_empty() {
+					n %= samples.len();
+				}
+				if n < samples.len() {
+					mix += samples[n] as f32 / i16::max_value() as f32;
+					*voice = Some(pos + rate as f64 / self.rate as f64);
+				}
+				else {
+					*voice = None;
+				}
+			}
+		}
+		mix.max(-1.0).min(1.0)
+	}
+}
+
+impl fmt::Debug for Mixer {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Mixer")
+	}
+}
+
+
+#[cfg(feature = "rust-audio")]
+#[derive(Debug)]
+pub struct RustSink {
+	mixer: Arc<Mutex<Mixer>>
+}
+
+#[cfg(feature = "rust-audio")]
+impl RustSink {
+	pub fn new() -> Option<RustSink> {
+		let device = cpal::default_output_device()?;
+		let format = device.default_output_format().ok()?;
+		let event_loop = cpal::EventLoop::new();
+		let stream = event_loop.build_output_stream(&device, &format).ok()?;
+		event_loop.play_stream(stream);
+
+		let mixer = Arc::new(Mutex::new(Mixer::new(format.sample_rate.0)));
+		let channels = format.channels as usize;
+		let shared = mixer.clone();
+		thread::spawn(move || {
+			event_loop.run(move |_, data| {
+				let mut mixer = shared.lock().unwrap();
+				match data {
+					StreamData::Output {buffer: UnknownTypeOutputBuffer::F32(mut buffer)} => {
+						for frame in buffer.chunks_mut(channels) {
+							let v = mixer.next();
+							for s in frame.iter_mut() { *s = v; }
+						}
+					},
+					StreamData::Output {buffer: UnknownTypeOutputBuffer::I16(mut buffer)} => {
+						for frame in buffer.chunks_mut(channels) {
+							let v = (mixer.next() * i16::max_value() as f32) as i16;
+							for s in frame.iter_mut() { *s = v; }
+						}
+					},
+					StreamData::Output {buffer: UnknownTypeOutputBuffer::U16(mut buffer)} => {
+						for frame in buffer.chunks_mut(channels) {
+							let v = ((mixer.next() + 1.0) * i16::max_value() as f32) as u16;
+							for s in frame.iter_mut() { *s = v; }
+						}
+					},
+					_ => ()
+				}
+			});
+		});
+
+		Some(RustSink {
+			mixer
+		})
+	}
+}
+
+#[cfg(feature = "rust-audio")]
+impl AudioSink for RustSink {
+	fn start(&mut self, sound: usize, _cycle: u64) {
+		self.mixer.lock().unwrap().start(sound);
+	}
+
+	fn stop(&mut self, sound: usize, _cycle: u64) {
+		self.mixer.lock().unwrap().stop(sound);
+	}
+}
+
+#[cfg(feature = "rust-audio")]
+fn rust_sink() -> Box<dyn AudioSink> {
+	match RustSink::new() {
+		Some(sink) => Box::new(sink),
+		None => {
+			println!("=> No audio output device available");
+			Box::new(NullSink)
+		}
+	}
+}
+
+#[cfg(not(feature = "rust-audio"))]
+fn rust_sink() -> Box<dyn AudioSink> {
+	println!("=> Built without the rust-audio feature");
+	Box::new(NullSink)
+}
+
+
+#[cfg(feature = "audio")]
+pub struct EarsSink {
+	sounds: Vec<Option<Sound>>
+}
+
+#[cfg(feature = "audio")]
+impl EarsSink {
+	pub fn new() -> EarsSink {
+		let mut sounds: Vec<Option<Sound>> = Vec::new();
+		for i in 0..synth::SOUND_COUNT {
+			let mut file = sound_file(i);
+			if !Path::new(&file).exists() {
+				// fall back to a synthesized version of the sample
+				let tmp = env::temp_dir().join(format!("emu8080-{}.wav", i));
+				file = tmp.to_string_lossy().into_owned();
+				if let Err(e) = synth::write_wav(&file, &synth::sound(i)) {
+					println!("=> Can't write synthesized sound {}: {}", file, e);
+				}
+			}
+
+			let sound = Sound::new(&file);
+			if sound.is_none() {
+				println!("=> Can't load sound file: {}", file);
+			}
+			sounds.push(sound);
+		}
+		for (i, sound) in sounds.iter_mut().enumerate() {
+			if let Some(ref mut sound) = *sound {
+				sound.set_looping(is_looping(i));
+			}
+		}
+
+		EarsSink {
+			sounds
+		}
+	}
+}
+
+#[cfg(feature = "audio")]
+impl AudioSink for EarsSink {
+	fn start(&mut self, i: usize, _cycle: u64) {
+		if let Some(ref mut sound) = self.sounds[i] {
+			// restart one-shots that are still playing
+			if sound.is_playing() {
+				sound.stop();
+			}
+			sound.play();
+		}
+	}
+
+	fn stop(&mut self, i: usize, _cycle: u64) {
+		if let Some(ref mut sound) = self.sounds[i] {
+			if sound.is_playing() {
+				sound.stop();
+			}
+		}
+	}
+}
+
+#[cfg(feature = "audio")]
+impl fmt::Debug for EarsSink {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "EarsSink")
+	}
+}
+
+#[cfg(feature = "audio")]
+fn ears_sink() -> Box<dyn AudioSink> {
+	Box::new(EarsSink::new())
+}
+
+#[cfg(not(feature = "audio"))]
+fn ears_sink() -> Box<dyn AudioSink> {
+	println!("=> Built without the audio feature");
+	Box::new(NullSink)
+}
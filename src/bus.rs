@@ -0,0 +1,104 @@
+// Memory as the CPU sees it. Addresses are 16 bit, so accesses can't leave the address space.
+pub trait Bus {
+	fn read(&mut self, addr: u16) -> u8;
+	fn write(&mut self, addr: u16, val: u8);
+}
+
+impl Bus for Vec<u8> {
+	#[inline]
+	fn read(&mut self, addr: u16) -> u8 {
+		self[addr as usize]
+	}
+
+	#[inline]
+	fn write(&mut self, addr: u16, val: u8) {
+		self[addr as usize] = val;
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+	Read,
+	Write,
+	// the address is the port
+	IoRead,
+	IoWrite
+}
+
+// One transfer on the bus, in the order the CPU does them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+	pub addr: u16,
+	pub value: u8,
+	pub kind: AccessKind
+}
+
+impl Access {
+	pub fn is_write(&self) -> bool {
+		self.kind == AccessKind::Write || self.kind == AccessKind::IoWrite
+	}
+
+	pub fn is_io(&self) -> bool {
+		self.kind == AccessKind::IoRead || self.kind == AccessKind::IoWrite
+	}
+}
+
+// Logs the reads of an instruction fetch, len of the bytes at pc.
+pub fn log_fetch(log: &mut Vec<Access>, pc: u16, bytes: [u8; 3], len: u8) {
+	for (i, &value) in bytes.iter().take(len as usize).enumerate() {
+		log.push(Access {addr: pc.wrapping_add(i as u16), value, kind: AccessKind::Read});
+	}
+}
+
+// Memory that records every access to it.
+pub struct LoggingBus<'a> {
+	mem: &'a mut Vec<u8>,
+	log: &'a mut Vec<Access>
+}
+
+impl<'a> LoggingBus<'a> {
+	pub fn new(mem: &'a mut Vec<u8>, log: &'a mut Vec<Access>) -> LoggingBus<'a> {
+		LoggingBus {mem, log}
+	}
+}
+
+impl<'a> Bus for LoggingBus<'a> {
+	fn read(&mut self, addr: u16) -> u8 {
+		let value = self.mem[addr as usize];
+		self.log.push(Access {addr, value, kind: AccessKind::Read});
+		value
+	}
+
+	fn write(&mut self, addr: u16, val: u8) {
+		self.mem[addr as usize] = val;
+		self.log.push(Access {addr, value: val, kind: AccessKind::Write});
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn logging_bus() {
+		let mut mem = vec![0; 0x10000];
+		mem[0xFFFF] = 0x12;
+		let mut log = Vec::new();
+		log_fetch(&mut log, 0xFFFF, [0x3A, 0x34, 0x12], 3);
+		{
+			let mut bus = LoggingBus::new(&mut mem, &mut log);
+			assert_eq!(bus.read(0xFFFF), 0x12);
+			bus.write(0x0000, 0x56);
+		}
+		assert_eq!(mem[0x0000], 0x56);
+		assert_eq!(log, vec![
+			Access {addr: 0xFFFF, value: 0x3A, kind: AccessKind::Read},
+			Access {addr: 0x0000, value: 0x34, kind: AccessKind::Read},
+			Access {addr: 0x0001, value: 0x12, kind: AccessKind::Read},
+			Access {addr: 0xFFFF, value: 0x12, kind: AccessKind::Read},
+			Access {addr: 0x0000, value: 0x56, kind: AccessKind::Write}
+		]);
+		assert!(log[4].is_write() && !log[4].is_io());
+	}
+}
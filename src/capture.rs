@@ -0,0 +1,90 @@
+use image::RgbaImage;
+use std::fs;
+use std::io::*;
+use std::path::Path;
+
+use synth;
+use sound::{self, SoundEvent};
+use machine::{CPU_HZ, CYCLES_PER_FRAME};
+
+pub fn save_png(img: &RgbaImage, filename: &str) -> Result<()> {
+	img.save(filename)
+}
+
+// the name with the first number that doesn't exist yet
+fn first_free<F: Fn(u32) -> String>(name: F) -> String {
+	(0..).map(name).find(|path| !Path::new(path).exists()).unwrap()
+}
+
+// saves to <dir>/shot-<n>.png with the first free number
+pub fn screenshot(img: &RgbaImage, dir: &str) -> Result<String> {
+	fs::create_dir_all(dir)?;
+	let filename = first_free(|n| format!("{}/shot-{:04}.png", dir, n));
+	save_png(img, &filename)?;
+	Ok(filename)
+}
+
+// <dir>/<n> with the first free number, for a new recording
+pub fn recording_dir(dir: &str) -> String {
+	first_free(|n| format!("{}/{:04}", dir, n))
+}
+
+
+// Writes one PNG per emulated frame (frame-00000.png, ...) and the audio as audio.wav.
+// Frames are taken by emulated time: if the window renders less often than the machine runs,
+// the last picture is repeated, if it renders more often, pictures are dropped.
+#[derive(Debug)]
+pub struct Recorder {
+	dir: String,
+	start_cycle: u64,
+	frames: u64,
+	audio: bool
+}
+
+impl Recorder {
+	// refuses to mix the frames into those of an earlier recording
+	pub fn new(dir: &str, start_cycle: u64, audio: bool) -> Result<Recorder> {
+		fs::create_dir_all(dir)?;
+		if fs::read_dir(dir)?.next().is_some() {
+			return Err(Error::new(ErrorKind::AlreadyExists, format!("{} is not empty", dir)));
+		}
+		Ok(Recorder {
+			dir: dir.to_string(),
+			start_cycle,
+			frames: 0,
+			audio
+		})
+	}
+
+	pub fn frames(&self) -> u64 {
+		self.frames
+	}
+
+	pub fn dir(&self) -> &str {
+		&self.dir
+	}
+
+	// writes the frames due up to the given machine cycle
+	pub fn capture(&mut self, cycle: u64, img: &RgbaImage) -> Result<()> {
+		let due = (cycle.saturating_sub(self.start_cycle)) / CYCLES_PER_FRAME;
+		while self.frames < due {
+			save_png(img, &format!("{}/frame-{:05}.png", self.dir, self.frames))?;
+			self.frames += 1;
+		}
+		Ok(())
+	}
+
+	// writes the audio of the recorded frames, events are those recorded by the machine
+	pub fn finish(self, events: &[SoundEvent]) -> Result<()> {
+		if !self.audio {
+			return Ok(());
+		}
+		let start = self.start_cycle;
+		let events: Vec<SoundEvent> = events.iter()
+			.filter(|e| e.cycle >= start)
+			.map(|e| SoundEvent {cycle: e.cycle - start, sound: e.sound, on: e.on})
+			.collect();
+		let samples = sound::render(&events, self.frames * CYCLES_PER_FRAME, CPU_HZ);
+		synth::write_wav(&format!("{}/audio.wav", self.dir), &samples)
+	}
+}
@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+use toml;
+
+use machine::{Machine, Speed, SCREEN_HZ};
+
+// further behind than this many frames of real time, the backlog is dropped instead of caught up
+const MAX_FRAMES_BEHIND: u64 = 4;
+// real time spent running frames per call when unthrottled
+const UNTHROTTLED_MILLIS: u64 = 15;
+
+// Paces emulated frames against the monotonic clock.
+// Frame times are counted from a fixed start, so rounding doesn't drift.
+// Sounds are started as the game triggers them, so audio stays in sync
+// as long as the emulation stays close to real time, which is what this ensures.
+#[derive(Debug)]
+pub struct FrameClock {
+	start: Instant,
+	// frames handed out since start
+	frames: u64,
+	// frames dropped because the host couldn't keep up
+	skipped: u64,
+	// speed the frames are counted at, restarts when it changes
+	speed: Speed
+}
+
+impl FrameClock {
+	pub fn new() -> FrameClock {
+		FrameClock {
+			start: Instant::now(),
+			frames: 0,
+			skipped: 0,
+			speed: Speed::Factor(1.0)
+		}
+	}
+
+	// runs the frames due now, honouring pause and speed of the machine
+	pub fn run(&mut self, m: &mut Machine) {
+		if m.is_paused() {
+			// don't catch up on the time spent paused
+			self.reset();
+			return;
+		}
+		if m.speed() != self.speed {
+			self.speed = m.speed();
+			self.reset();
+		}
+
+		match self.speed {
+			Speed::Factor(factor) => {
+				// only the last of several frames is shown, the others are skipped
+				for _ in 0..self.frames_due(factor) {
+					m.run_frame();
+				}
+			},
+			Speed::Unthrottled => {
+				let start = Instant::now();
+				while start.elapsed() < Duration::from_millis(UNTHROTTLED_MILLIS) {
+					m.run_frame();
+				}
+			}
+		}
+	}
+
+	// number of frames that should be run now
+	pub fn frames_due(&mut self, factor: f64) -> u64 {
+		let elapsed = self.start.elapsed();
+		self.frames_due_after(elapsed, factor)
+	}
+
+	fn frames_due_after(&mut self, elapsed: Duration, factor: f64) -> u64 {
+		let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+		let target = (nanos as f64 * factor * SCREEN_HZ as f64 / 1_000_000_000.0) as u64;
+
+		let mut due = target.saturating_sub(self.frames);
+		// when fast-forwarding, more frames are due each time
+		let max_behind = MAX_FRAMES_BEHIND * factor.ceil().max(1.0) as u64;
+		if due > max_behind {
+			// e.g. after the window was dragged, catching up would play a burst
+			self.skipped += due - 1;
+			self.frames += due - 1;
+			due = 1;
+		}
+		self.frames += due;
+		due
+	}
+
+	pub fn skipped(&self) -> u64 {
+		self.skipped
+	}
+
+	// starts counting anew, e.g. after the emulation was paused
+	pub fn reset(&mut self) {
+		self.start = Instant::now();
+		self.frames = 0;
+	}
+}
+
+
+// Speeds the speed key steps through, after real speed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeedConfig {
+	pub steps: Vec<Speed>
+}
+
+impl SpeedConfig {
+	pub fn new() -> SpeedConfig {
+		SpeedConfig {
+			steps: vec![Speed::Factor(2.0), Speed::Factor(4.0), Speed::Unthrottled, Speed::Factor(0.5), Speed::Factor(0.25)]
+		}
+	}
+
+	// `steps` lists factors or "unthrottled"
+	pub fn from_table(table: &toml::value::Table) -> SpeedConfig {
+		let mut config = SpeedConfig::new();
+
+		if let Some(steps) = table.get("steps").and_then(|v| v.as_array()) {
+			config.steps = steps.iter().filter_map(|v| match *v {
+				toml::Value::Float(f) if f > 0.0 => Some(Speed::Factor(f)),
+				toml::Value::Integer(i) if i > 0 => Some(Speed::Factor(i as f64)),
+				toml::Value::String(ref s) if s == "unthrottled" => Some(Speed::Unthrottled),
+				ref other => {
+					println!("=> Ignoring invalid speed: {}", other);
+					None
+				}
+			}).collect();
+		}
+		config
+	}
+
+	pub fn to_table(&self) -> toml::value::Table {
+		let mut table = toml::value::Table::new();
+		table.insert("steps".to_string(), toml::Value::Array(self.steps.iter().map(|s| match *s {
+			Speed::Factor(f) => toml::Value::Float(f),
+			Speed::Unthrottled => toml::Value::String("unthrottled".to_string())
+		}).collect()));
+		table
+	}
+
+	// the step after the given speed, real speed after the last one
+	pub fn next(&self, speed: Speed) -> Speed {
+		let normal = Speed::Factor(1.0);
+		match self.steps.iter().position(|&s| s == speed) {
+			Some(i) if i + 1 < self.steps.len() => self.steps[i + 1],
+			Some(_) => normal,
+			None => self.steps.first().cloned().unwrap_or(normal)
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn millis(ms: u64) -> Duration {
+		Duration::from_millis(ms)
+	}
+
+	#[test]
+	fn real_speed() {
+		let mut clock = FrameClock::new();
+		assert_eq!(clock.frames_due_after(millis(10), 1.0), 0);
+		assert_eq!(clock.frames_due_after(millis(20), 1.0), 1);
+		assert_eq!(clock.frames_due_after(millis(50), 1.0), 2);
+		// a stall drops the backlog
+		assert_eq!(clock.frames_due_after(millis(1050), 1.0), 1);
+		assert_eq!(clock.skipped(), 59);
+	}
+
+	#[test]
+	fn fast_forward() {
+		// updates at 60 Hz run 10 frames each at 10x, nothing is dropped
+		let mut clock = FrameClock::new();
+		let mut frames = 0;
+		for update in 1..61 {
+			frames += clock.frames_due_after(millis(update * 1000 / 60), 10.0);
+		}
+		assert_eq!((frames, clock.skipped()), (600, 0));
+
+		assert_eq!(clock.frames_due_after(millis(2000), 10.0), 1);
+		assert_eq!(clock.skipped(), 599);
+	}
+}
@@ -0,0 +1,160 @@
+use std::io::*;
+use std::fs::File;
+use toml;
+
+use input::{Bindings, PadBindings};
+use audio::AudioBackend;
+use display::DisplayConfig;
+use profile::Profile;
+use artwork::ArtworkConfig;
+use view::ViewConfig;
+use filter::FilterConfig;
+use clock::SpeedConfig;
+use trace::TraceConfig;
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+
+#[derive(Debug)]
+pub struct Config {
+	pub bindings: Bindings,
+	pub gamepad: PadBindings,
+	pub audio: AudioBackend,
+	pub profile: Profile,
+	pub display: DisplayConfig,
+	pub artwork: ArtworkConfig,
+	pub view: ViewConfig,
+	pub filters: FilterConfig,
+	pub speed: SpeedConfig,
+	// tracing is on when the section is present
+	pub trace: Option<TraceConfig>,
+	// table cabinet with a control panel per player and the screen flipped for player 2
+	pub cocktail: bool
+}
+
+impl Config {
+	pub fn new() -> Config {
+		Config {
+			bindings: Bindings::new(),
+			gamepad: PadBindings::new(),
+			audio: AudioBackend::default(),
+			profile: Profile::default(),
+			display: Profile::default().display,
+			artwork: Profile::default().artwork,
+			view: ViewConfig::new(),
+			filters: FilterConfig::new(),
+			speed: SpeedConfig::new(),
+			trace: None,
+			cocktail: false
+		}
+	}
+
+	pub fn load(filename: &str) -> Config {
+		let mut content = String::new();
+		if let Err(_) = File::open(filename).and_then(|mut f| f.read_to_string(&mut content)) {
+			println!("=> No config file found, using defaults");
+			return Config::new();
+		}
+
+		let value = match content.parse::<toml::Value>() {
+			Ok(value) => value,
+			Err(e) => {
+				println!("=> Invalid config file {}: {}", filename, e);
+				return Config::new();
+			}
+		};
+
+		let mut config = Config::new();
+		if let Some(name) = value.get("profile").and_then(|v| v.as_str()) {
+			match Profile::builtin(name) {
+				Some(profile) => config.profile = profile,
+				None => println!("=> Unknown profile: {}", name)
+			}
+		}
+		if let Some(cocktail) = value.get("cocktail").and_then(|v| v.as_bool()) {
+			config.cocktail = cocktail;
+		}
+		config.display = config.profile.display.clone();
+		config.artwork = config.profile.artwork.clone();
+
+		if let Some(table) = value.get("bindings").and_then(|v| v.as_table()) {
+			config.bindings = Bindings::from_table(table);
+		}
+		if let Some(table) = value.get("gamepad").and_then(|v| v.as_table()) {
+			config.gamepad = PadBindings::from_table(table);
+		}
+		if let Some(table) = value.get("audio").and_then(|v| v.as_table()) {
+			config.audio = audio_from_table(table);
+		}
+		if let Some(table) = value.get("display").and_then(|v| v.as_table()) {
+			config.display = DisplayConfig::from_table(table, &config.display);
+		}
+		if let Some(table) = value.get("artwork").and_then(|v| v.as_table()) {
+			config.artwork = ArtworkConfig::from_table(table, &config.artwork);
+		}
+		if let Some(table) = value.get("view").and_then(|v| v.as_table()) {
+			config.view = ViewConfig::from_table(table);
+		}
+		if let Some(table) = value.get("filters").and_then(|v| v.as_table()) {
+			config.filters = FilterConfig::from_table(table);
+		}
+		if let Some(table) = value.get("speed").and_then(|v| v.as_table()) {
+			config.speed = SpeedConfig::from_table(table);
+		}
+		if let Some(table) = value.get("trace").and_then(|v| v.as_table()) {
+			config.trace = Some(TraceConfig::from_table(table));
+		}
+		println!("=> Config loaded from {}", filename);
+		config
+	}
+
+	pub fn save(&self, filename: &str) -> Result<()> {
+		let mut root = toml::value::Table::new();
+		root.insert("profile".to_string(), toml::Value::String(self.profile.name.clone()));
+		root.insert("cocktail".to_string(), toml::Value::Boolean(self.cocktail));
+		if self.display != self.profile.display {
+			root.insert("display".to_string(), toml::Value::Table(self.display.to_table()));
+		}
+		if self.artwork != self.profile.artwork {
+			root.insert("artwork".to_string(), toml::Value::Table(self.artwork.to_table()));
+		}
+		root.insert("bindings".to_string(), toml::Value::Table(self.bindings.to_table()));
+		root.insert("gamepad".to_string(), toml::Value::Table(self.gamepad.to_table()));
+		root.insert("view".to_string(), toml::Value::Table(self.view.to_table()));
+		root.insert("filters".to_string(), toml::Value::Table(self.filters.to_table()));
+		root.insert("speed".to_string(), toml::Value::Table(self.speed.to_table()));
+		if let Some(ref trace) = self.trace {
+			root.insert("trace".to_string(), toml::Value::Table(trace.to_table()));
+		}
+		root.insert("audio".to_string(), toml::Value::Table(audio_to_table(&self.audio)));
+
+		let content = toml::to_string(&toml::Value::Table(root))
+			.map_err(|e| Error::new(ErrorKind::Other, e))?;
+		let mut file = File::create(filename)?;
+		file.write_all(content.as_bytes())
+	}
+}
+
+fn audio_from_table(table: &toml::value::Table) -> AudioBackend {
+	let file = table.get("file").and_then(|v| v.as_str()).unwrap_or("audio.wav");
+	match table.get("backend").and_then(|v| v.as_str()) {
+		Some("rust") => AudioBackend::Rust,
+		Some("ears") => AudioBackend::Ears,
+		Some("null") => AudioBackend::Null,
+		Some("file") => AudioBackend::File(file.to_string()),
+		Some(other) => {
+			println!("=> Unknown audio backend: {}", other);
+			AudioBackend::default()
+		},
+		None => AudioBackend::default()
+	}
+}
+
+fn audio_to_table(backend: &AudioBackend) -> toml::value::Table {
+	let mut table = toml::value::Table::new();
+	table.insert("backend".to_string(), toml::Value::String(backend.name().to_string()));
+	if let AudioBackend::File(ref file) = *backend {
+		table.insert("file".to_string(), toml::Value::String(file.clone()));
+	}
+	table
+}
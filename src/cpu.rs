@@ -1,4 +1,5 @@
 use alu::ALU;
+use bus::Bus;
 
 macro_rules! append {
 	($x:expr, $y:expr) => (((($x) as u16) << 8) | (($y) as u16))
@@ -43,11 +44,16 @@ impl CPU {
 			self.h, self.l,
 			self.pc,
 			self.sp);
-		println!("\t{}{}{}{}{}", if self.cc.z == 1 {'z'} else {'.'},
-			if self.cc.s == 1 {'s'} else {'.'},
-			if self.cc.p == 1 {'p'} else {'.'},
-			if self.cc.cy == 1 {'c'} else {'.'},
-			if self.cc.ac == 1 {'a'} else {'.'});
+		println!("\t{}{}{}{}{}", if self.cc.z() == 1 {'z'} else {'.'},
+			if self.cc.s() == 1 {'s'} else {'.'},
+			if self.cc.p() == 1 {'p'} else {'.'},
+			if self.cc.cy() == 1 {'c'} else {'.'},
+			if self.cc.ac() == 1 {'a'} else {'.'});
+	}
+
+	// AF BC DE HL PC SP, in the order print_state shows them
+	pub fn registers(&self) -> [u16; 6] {
+		[append!(self.a, self.cc.get_psw()), self.get_bc(), self.get_de(), self.get_hl(), self.pc, self.sp]
 	}
 
 	pub fn get_hl(&self) -> u16 {
@@ -77,71 +83,87 @@ impl CPU {
 		self.e = splitr!(val);
 	}
 
-	pub fn from_hl(&self, mem: &Vec<u8>) -> u8 {
-		mem[self.get_hl() as usize]
+	pub fn from_hl<B: Bus>(&self, mem: &mut B) -> u8 {
+		mem.read(self.get_hl())
 	}
 
-	pub fn to_hl(&self, mem: &mut Vec<u8>, val: u8) {
-		mem[self.get_hl() as usize] = val;
+	pub fn to_hl<B: Bus>(&self, mem: &mut B, val: u8) {
+		mem.write(self.get_hl(), val);
 	}
 }
 
-fn parity(mut byte: u8) -> u8 {
-	let mut result = 1;
-
-	while byte != 0 {
-		result ^= 1;
-		byte &= byte - 1;
-	}
-
-	result
-}
-
+// flag bits, in their positions in the PSW
+pub const FLAG_S: u8 = 0x80;
+pub const FLAG_Z: u8 = 0x40;
+pub const FLAG_AC: u8 = 0x10;
+pub const FLAG_P: u8 = 0x04;
+pub const FLAG_CY: u8 = 0x01;
+const FLAG_MASK: u8 = FLAG_S | FLAG_Z | FLAG_AC | FLAG_P | FLAG_CY;
+
+// sign, zero and parity flags of every byte
+static SZP: [u8; 256] = [
+	0x44, 0x00, 0x00, 0x04, 0x00, 0x04, 0x04, 0x00, 0x00, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00, 0x04,
+	0x00, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00, 0x04, 0x04, 0x00, 0x00, 0x04, 0x00, 0x04, 0x04, 0x00,
+	0x00, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00, 0x04, 0x04, 0x00, 0x00, 0x04, 0x00, 0x04, 0x04, 0x00,
+	0x04, 0x00, 0x00, 0x04, 0x00, 0x04, 0x04, 0x00, 0x00, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00, 0x04,
+	0x00, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00, 0x04, 0x04, 0x00, 0x00, 0x04, 0x00, 0x04, 0x04, 0x00,
+	0x04, 0x00, 0x00, 0x04, 0x00, 0x04, 0x04, 0x00, 0x00, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00, 0x04,
+	0x04, 0x00, 0x00, 0x04, 0x00, 0x04, 0x04, 0x00, 0x00, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00, 0x04,
+	0x00, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00, 0x04, 0x04, 0x00, 0x00, 0x04, 0x00, 0x04, 0x04, 0x00,
+	0x80, 0x84, 0x84, 0x80, 0x84, 0x80, 0x80, 0x84, 0x84, 0x80, 0x80, 0x84, 0x80, 0x84, 0x84, 0x80,
+	0x84, 0x80, 0x80, 0x84, 0x80, 0x84, 0x84, 0x80, 0x80, 0x84, 0x84, 0x80, 0x84, 0x80, 0x80, 0x84,
+	0x84, 0x80, 0x80, 0x84, 0x80, 0x84, 0x84, 0x80, 0x80, 0x84, 0x84, 0x80, 0x84, 0x80, 0x80, 0x84,
+	0x80, 0x84, 0x84, 0x80, 0x84, 0x80, 0x80, 0x84, 0x84, 0x80, 0x80, 0x84, 0x80, 0x84, 0x84, 0x80,
+	0x84, 0x80, 0x80, 0x84, 0x80, 0x84, 0x84, 0x80, 0x80, 0x84, 0x84, 0x80, 0x84, 0x80, 0x80, 0x84,
+	0x80, 0x84, 0x84, 0x80, 0x84, 0x80, 0x80, 0x84, 0x84, 0x80, 0x80, 0x84, 0x80, 0x84, 0x84, 0x80,
+	0x80, 0x84, 0x84, 0x80, 0x84, 0x80, 0x80, 0x84, 0x84, 0x80, 0x80, 0x84, 0x80, 0x84, 0x84, 0x80,
+	0x84, 0x80, 0x80, 0x84, 0x80, 0x84, 0x84, 0x80, 0x80, 0x84, 0x84, 0x80, 0x84, 0x80, 0x80, 0x84
+];
+
+// The flags packed like in the PSW, so PUSH PSW and the lookups need no shifting.
 #[derive(Debug)]
 pub struct ConditionCodes {
-	pub z: u8,
-	pub s: u8,
-	pub p: u8,
-	pub cy: u8,
-	pub ac: u8
+	flags: u8
 }
 
 impl ConditionCodes {
 	pub fn new() -> ConditionCodes {
-		ConditionCodes {z: 0, s: 0, p: 0, cy: 0, ac: 0}
+		ConditionCodes {flags: 0}
+	}
+
+	pub fn z(&self) -> u8 { (self.flags >> 6) & 1 }
+	pub fn s(&self) -> u8 { self.flags >> 7 }
+	pub fn p(&self) -> u8 { (self.flags >> 2) & 1 }
+	pub fn cy(&self) -> u8 { self.flags & 1 }
+	pub fn ac(&self) -> u8 { (self.flags >> 4) & 1 }
+
+	pub fn set_z(&mut self, v: u8) { self.set(FLAG_Z, v) }
+	pub fn set_s(&mut self, v: u8) { self.set(FLAG_S, v) }
+	pub fn set_p(&mut self, v: u8) { self.set(FLAG_P, v) }
+	pub fn set_cy(&mut self, v: u8) { self.set(FLAG_CY, v) }
+	pub fn set_ac(&mut self, v: u8) { self.set(FLAG_AC, v) }
+
+	fn set(&mut self, flag: u8, v: u8) {
+		if v & 1 == 1 { self.flags |= flag; } else { self.flags &= !flag; }
 	}
 
 	pub fn get_psw(&self) -> u8 {
-		self.cy | self.p << 2 | self.ac << 4 | self.z << 6 | self.s << 7 | 2
+		self.flags | 2
 	}
 
 	pub fn set_psw(&mut self, psw: u8) {
-		self.cy = psw & 1;
-		self.p = (psw >> 2) & 1;
-		self.ac = (psw >> 4) & 1;
-		self.z = (psw >> 6) & 1;
-		self.s = (psw >> 7) & 1;
+		self.flags = psw & FLAG_MASK;
 	}
 
 	pub fn set_arith(&mut self, val: u8, alu: &ALU) {
-		self.z = (val == 0) as u8;
-		self.s = (val & 0x80) >> 7;
-		self.p = parity(val);
-		self.cy = alu.cy as u8;
-		self.ac = alu.ac as u8;
+		self.flags = SZP[val as usize] | alu.cy as u8 | (alu.ac as u8) << 4;
 	}
 
 	pub fn set_zsp(&mut self, val: u8, alu: &ALU) {
-		self.z = (val == 0) as u8;
-		self.s = (val & 0x80) >> 7;
-		self.p = parity(val);
-		self.ac = alu.ac as u8;
+		self.flags = SZP[val as usize] | (self.flags & FLAG_CY) | (alu.ac as u8) << 4;
 	}
 
 	pub fn set_logic(&mut self, val: u8) {
-		self.cy = 0; self.ac = 0;
-		self.z = (val == 0) as u8;
-		self.s = (val & 0x80) >> 7;
-		self.p = parity(val);
+		self.flags = SZP[val as usize];
 	}
 }
@@ -1,279 +1,310 @@
+use std::borrow::Cow;
+
 pub fn print_instr(opcode: u8, arg1: u8, arg2: u8, pc: u16) -> u8 {
-	print!("{:04X} ", pc);
-	//print!("{:04X}: {:02X}\t", pc, opcode);
-	let opbytes = match	opcode {
-		0x00 => {print!("NOP"); 1},
-		0x01 => {print!("LXI    B,#${:02X}{:02X}", arg2, arg1); 3},
-		0x02 => {print!("STAX   B"); 1},
-		0x03 => {print!("INX    B"); 1},
-		0x04 => {print!("INR    B"); 1},
-		0x05 => {print!("DCR    B"); 1},
-		0x06 => {print!("MVI    B,#${:02X}", arg1); 2},
-		0x07 => {print!("RLC"); 1},
-		0x08 => {print!("NOP"); 1},
-		0x09 => {print!("DAD    B"); 1},
-		0x0a => {print!("LDAX   B"); 1},
-		0x0b => {print!("DCX    B"); 1},
-		0x0c => {print!("INR    C"); 1},
-		0x0d => {print!("DCR    C"); 1},
-		0x0e => {print!("MVI    C,#${:02X}", arg1); 2},
-		0x0f => {print!("RRC"); 1},
+	let (text, opbytes) = disassemble(opcode, arg1, arg2);
+	print!("{:04X} {}", pc, text);
+	opbytes
+}
+
+// mnemonic and length in bytes of the instruction, only instructions with operands allocate
+pub fn disassemble(opcode: u8, arg1: u8, arg2: u8) -> (Cow<'static, str>, u8) {
+	match opcode {
+		0x00 => (Cow::Borrowed("NOP"), 1),
+		0x01 => (Cow::Owned(format!("LXI    B,#${:02X}{:02X}", arg2, arg1)), 3),
+		0x02 => (Cow::Borrowed("STAX   B"), 1),
+		0x03 => (Cow::Borrowed("INX    B"), 1),
+		0x04 => (Cow::Borrowed("INR    B"), 1),
+		0x05 => (Cow::Borrowed("DCR    B"), 1),
+		0x06 => (Cow::Owned(format!("MVI    B,#${:02X}", arg1)), 2),
+		0x07 => (Cow::Borrowed("RLC"), 1),
+		0x08 => (Cow::Borrowed("NOP"), 1),
+		0x09 => (Cow::Borrowed("DAD    B"), 1),
+		0x0a => (Cow::Borrowed("LDAX   B"), 1),
+		0x0b => (Cow::Borrowed("DCX    B"), 1),
+		0x0c => (Cow::Borrowed("INR    C"), 1),
+		0x0d => (Cow::Borrowed("DCR    C"), 1),
+		0x0e => (Cow::Owned(format!("MVI    C,#${:02X}", arg1)), 2),
+		0x0f => (Cow::Borrowed("RRC"), 1),
 			
-		0x10 => {print!("NOP"); 1},
-		0x11 => {print!("LXI    D,#${:02X}{:02X}", arg2, arg1); 3},
-		0x12 => {print!("STAX   D"); 1},
-		0x13 => {print!("INX    D"); 1},
-		0x14 => {print!("INR    D"); 1},
-		0x15 => {print!("DCR    D"); 1},
-		0x16 => {print!("MVI    D,#${:02X}", arg1); 2},
-		0x17 => {print!("RAL"); 1},
-		0x18 => {print!("NOP"); 1},
-		0x19 => {print!("DAD    D"); 1},
-		0x1a => {print!("LDAX   D"); 1},
-		0x1b => {print!("DCX    D"); 1},
-		0x1c => {print!("INR    E"); 1},
-		0x1d => {print!("DCR    E"); 1},
-		0x1e => {print!("MVI    E,#${:02X}", arg1); 2},
-		0x1f => {print!("RAR"); 1},
+		0x10 => (Cow::Borrowed("NOP"), 1),
+		0x11 => (Cow::Owned(format!("LXI    D,#${:02X}{:02X}", arg2, arg1)), 3),
+		0x12 => (Cow::Borrowed("STAX   D"), 1),
+		0x13 => (Cow::Borrowed("INX    D"), 1),
+		0x14 => (Cow::Borrowed("INR    D"), 1),
+		0x15 => (Cow::Borrowed("DCR    D"), 1),
+		0x16 => (Cow::Owned(format!("MVI    D,#${:02X}", arg1)), 2),
+		0x17 => (Cow::Borrowed("RAL"), 1),
+		0x18 => (Cow::Borrowed("NOP"), 1),
+		0x19 => (Cow::Borrowed("DAD    D"), 1),
+		0x1a => (Cow::Borrowed("LDAX   D"), 1),
+		0x1b => (Cow::Borrowed("DCX    D"), 1),
+		0x1c => (Cow::Borrowed("INR    E"), 1),
+		0x1d => (Cow::Borrowed("DCR    E"), 1),
+		0x1e => (Cow::Owned(format!("MVI    E,#${:02X}", arg1)), 2),
+		0x1f => (Cow::Borrowed("RAR"), 1),
 			
-		0x20 => {print!("NOP"); 1},
-		0x21 => {print!("LXI    H,#${:02X}{:02X}", arg2, arg1); 3},
-		0x22 => {print!("SHLD   ${:02X}{:02X}", arg2, arg1); 3},
-		0x23 => {print!("INX    H"); 1},
-		0x24 => {print!("INR    H"); 1},
-		0x25 => {print!("DCR    H"); 1},
-		0x26 => {print!("MVI    H,#${:02X}", arg1); 2},
-		0x27 => {print!("DAA"); 1},
-		0x28 => {print!("NOP"); 1},
-		0x29 => {print!("DAD    H"); 1},
-		0x2a => {print!("LHLD   ${:02X}{:02X}", arg2, arg1); 3},
-		0x2b => {print!("DCX    H"); 1},
-		0x2c => {print!("INR    L"); 1},
-		0x2d => {print!("DCR    L"); 1},
-		0x2e => {print!("MVI    L,#${:02X}", arg1); 2},
-		0x2f => {print!("CMA"); 1},
+		0x20 => (Cow::Borrowed("NOP"), 1),
+		0x21 => (Cow::Owned(format!("LXI    H,#${:02X}{:02X}", arg2, arg1)), 3),
+		0x22 => (Cow::Owned(format!("SHLD   ${:02X}{:02X}", arg2, arg1)), 3),
+		0x23 => (Cow::Borrowed("INX    H"), 1),
+		0x24 => (Cow::Borrowed("INR    H"), 1),
+		0x25 => (Cow::Borrowed("DCR    H"), 1),
+		0x26 => (Cow::Owned(format!("MVI    H,#${:02X}", arg1)), 2),
+		0x27 => (Cow::Borrowed("DAA"), 1),
+		0x28 => (Cow::Borrowed("NOP"), 1),
+		0x29 => (Cow::Borrowed("DAD    H"), 1),
+		0x2a => (Cow::Owned(format!("LHLD   ${:02X}{:02X}", arg2, arg1)), 3),
+		0x2b => (Cow::Borrowed("DCX    H"), 1),
+		0x2c => (Cow::Borrowed("INR    L"), 1),
+		0x2d => (Cow::Borrowed("DCR    L"), 1),
+		0x2e => (Cow::Owned(format!("MVI    L,#${:02X}", arg1)), 2),
+		0x2f => (Cow::Borrowed("CMA"), 1),
 			
-		0x30 => {print!("NOP"); 1},
-		0x31 => {print!("LXI    SP,#${:02X}{:02X}", arg2, arg1); 3},
-		0x32 => {print!("STA    ${:02X}{:02X}", arg2, arg1); 3},
-		0x33 => {print!("INX    SP"); 1},
-		0x34 => {print!("INR    M"); 1},
-		0x35 => {print!("DCR    M"); 1},
-		0x36 => {print!("MVI    M,#${:02X}", arg1); 2},
-		0x37 => {print!("STC"); 1},
-		0x38 => {print!("NOP"); 1},
-		0x39 => {print!("DAD    SP"); 1},
-		0x3a => {print!("LDA    ${:02X}{:02X}", arg2, arg1); 3},
-		0x3b => {print!("DCX    SP"); 1},
-		0x3c => {print!("INR    A"); 1},
-		0x3d => {print!("DCR    A"); 1},
-		0x3e => {print!("MVI    A,#${:02X}", arg1); 2},
-		0x3f => {print!("CMC"); 1},
+		0x30 => (Cow::Borrowed("NOP"), 1),
+		0x31 => (Cow::Owned(format!("LXI    SP,#${:02X}{:02X}", arg2, arg1)), 3),
+		0x32 => (Cow::Owned(format!("STA    ${:02X}{:02X}", arg2, arg1)), 3),
+		0x33 => (Cow::Borrowed("INX    SP"), 1),
+		0x34 => (Cow::Borrowed("INR    M"), 1),
+		0x35 => (Cow::Borrowed("DCR    M"), 1),
+		0x36 => (Cow::Owned(format!("MVI    M,#${:02X}", arg1)), 2),
+		0x37 => (Cow::Borrowed("STC"), 1),
+		0x38 => (Cow::Borrowed("NOP"), 1),
+		0x39 => (Cow::Borrowed("DAD    SP"), 1),
+		0x3a => (Cow::Owned(format!("LDA    ${:02X}{:02X}", arg2, arg1)), 3),
+		0x3b => (Cow::Borrowed("DCX    SP"), 1),
+		0x3c => (Cow::Borrowed("INR    A"), 1),
+		0x3d => (Cow::Borrowed("DCR    A"), 1),
+		0x3e => (Cow::Owned(format!("MVI    A,#${:02X}", arg1)), 2),
+		0x3f => (Cow::Borrowed("CMC"), 1),
 			
-		0x40 => {print!("MOV    B,B"); 1},
-		0x41 => {print!("MOV    B,C"); 1},
-		0x42 => {print!("MOV    B,D"); 1},
-		0x43 => {print!("MOV    B,E"); 1},
-		0x44 => {print!("MOV    B,H"); 1},
-		0x45 => {print!("MOV    B,L"); 1},
-		0x46 => {print!("MOV    B,M"); 1},
-		0x47 => {print!("MOV    B,A"); 1},
-		0x48 => {print!("MOV    C,B"); 1},
-		0x49 => {print!("MOV    C,C"); 1},
-		0x4a => {print!("MOV    C,D"); 1},
-		0x4b => {print!("MOV    C,E"); 1},
-		0x4c => {print!("MOV    C,H"); 1},
-		0x4d => {print!("MOV    C,L"); 1},
-		0x4e => {print!("MOV    C,M"); 1},
-		0x4f => {print!("MOV    C,A"); 1},
+		0x40 => (Cow::Borrowed("MOV    B,B"), 1),
+		0x41 => (Cow::Borrowed("MOV    B,C"), 1),
+		0x42 => (Cow::Borrowed("MOV    B,D"), 1),
+		0x43 => (Cow::Borrowed("MOV    B,E"), 1),
+		0x44 => (Cow::Borrowed("MOV    B,H"), 1),
+		0x45 => (Cow::Borrowed("MOV    B,L"), 1),
+		0x46 => (Cow::Borrowed("MOV    B,M"), 1),
+		0x47 => (Cow::Borrowed("MOV    B,A"), 1),
+		0x48 => (Cow::Borrowed("MOV    C,B"), 1),
+		0x49 => (Cow::Borrowed("MOV    C,C"), 1),
+		0x4a => (Cow::Borrowed("MOV    C,D"), 1),
+		0x4b => (Cow::Borrowed("MOV    C,E"), 1),
+		0x4c => (Cow::Borrowed("MOV    C,H"), 1),
+		0x4d => (Cow::Borrowed("MOV    C,L"), 1),
+		0x4e => (Cow::Borrowed("MOV    C,M"), 1),
+		0x4f => (Cow::Borrowed("MOV    C,A"), 1),
 			
-		0x50 => {print!("MOV    D,B"); 1},
-		0x51 => {print!("MOV    D,C"); 1},
-		0x52 => {print!("MOV    D,D"); 1},
-		0x53 => {print!("MOV    D.E"); 1},
-		0x54 => {print!("MOV    D,H"); 1},
-		0x55 => {print!("MOV    D,L"); 1},
-		0x56 => {print!("MOV    D,M"); 1},
-		0x57 => {print!("MOV    D,A"); 1},
-		0x58 => {print!("MOV    E,B"); 1},
-		0x59 => {print!("MOV    E,C"); 1},
-		0x5a => {print!("MOV    E,D"); 1},
-		0x5b => {print!("MOV    E,E"); 1},
-		0x5c => {print!("MOV    E,H"); 1},
-		0x5d => {print!("MOV    E,L"); 1},
-		0x5e => {print!("MOV    E,M"); 1},
-		0x5f => {print!("MOV    E,A"); 1},
+		0x50 => (Cow::Borrowed("MOV    D,B"), 1),
+		0x51 => (Cow::Borrowed("MOV    D,C"), 1),
+		0x52 => (Cow::Borrowed("MOV    D,D"), 1),
+		0x53 => (Cow::Borrowed("MOV    D.E"), 1),
+		0x54 => (Cow::Borrowed("MOV    D,H"), 1),
+		0x55 => (Cow::Borrowed("MOV    D,L"), 1),
+		0x56 => (Cow::Borrowed("MOV    D,M"), 1),
+		0x57 => (Cow::Borrowed("MOV    D,A"), 1),
+		0x58 => (Cow::Borrowed("MOV    E,B"), 1),
+		0x59 => (Cow::Borrowed("MOV    E,C"), 1),
+		0x5a => (Cow::Borrowed("MOV    E,D"), 1),
+		0x5b => (Cow::Borrowed("MOV    E,E"), 1),
+		0x5c => (Cow::Borrowed("MOV    E,H"), 1),
+		0x5d => (Cow::Borrowed("MOV    E,L"), 1),
+		0x5e => (Cow::Borrowed("MOV    E,M"), 1),
+		0x5f => (Cow::Borrowed("MOV    E,A"), 1),
             
-		0x60 => {print!("MOV    H,B"); 1},
-		0x61 => {print!("MOV    H,C"); 1},
-		0x62 => {print!("MOV    H,D"); 1},
-		0x63 => {print!("MOV    H.E"); 1},
-		0x64 => {print!("MOV    H,H"); 1},
-		0x65 => {print!("MOV    H,L"); 1},
-		0x66 => {print!("MOV    H,M"); 1},
-		0x67 => {print!("MOV    H,A"); 1},
-		0x68 => {print!("MOV    L,B"); 1},
-		0x69 => {print!("MOV    L,C"); 1},
-		0x6a => {print!("MOV    L,D"); 1},
-		0x6b => {print!("MOV    L,E"); 1},
-		0x6c => {print!("MOV    L,H"); 1},
-		0x6d => {print!("MOV    L,L"); 1},
-		0x6e => {print!("MOV    L,M"); 1},
-		0x6f => {print!("MOV    L,A"); 1},
+		0x60 => (Cow::Borrowed("MOV    H,B"), 1),
+		0x61 => (Cow::Borrowed("MOV    H,C"), 1),
+		0x62 => (Cow::Borrowed("MOV    H,D"), 1),
+		0x63 => (Cow::Borrowed("MOV    H.E"), 1),
+		0x64 => (Cow::Borrowed("MOV    H,H"), 1),
+		0x65 => (Cow::Borrowed("MOV    H,L"), 1),
+		0x66 => (Cow::Borrowed("MOV    H,M"), 1),
+		0x67 => (Cow::Borrowed("MOV    H,A"), 1),
+		0x68 => (Cow::Borrowed("MOV    L,B"), 1),
+		0x69 => (Cow::Borrowed("MOV    L,C"), 1),
+		0x6a => (Cow::Borrowed("MOV    L,D"), 1),
+		0x6b => (Cow::Borrowed("MOV    L,E"), 1),
+		0x6c => (Cow::Borrowed("MOV    L,H"), 1),
+		0x6d => (Cow::Borrowed("MOV    L,L"), 1),
+		0x6e => (Cow::Borrowed("MOV    L,M"), 1),
+		0x6f => (Cow::Borrowed("MOV    L,A"), 1),
             
-		0x70 => {print!("MOV    M,B"); 1},
-		0x71 => {print!("MOV    M,C"); 1},
-		0x72 => {print!("MOV    M,D"); 1},
-		0x73 => {print!("MOV    M.E"); 1},
-		0x74 => {print!("MOV    M,H"); 1},
-		0x75 => {print!("MOV    M,L"); 1},
-		0x76 => {print!("HLT");        1},
-		0x77 => {print!("MOV    M,A"); 1},
-		0x78 => {print!("MOV    A,B"); 1},
-		0x79 => {print!("MOV    A,C"); 1},
-		0x7a => {print!("MOV    A,D"); 1},
-		0x7b => {print!("MOV    A,E"); 1},
-		0x7c => {print!("MOV    A,H"); 1},
-		0x7d => {print!("MOV    A,L"); 1},
-		0x7e => {print!("MOV    A,M"); 1},
-		0x7f => {print!("MOV    A,A"); 1},
+		0x70 => (Cow::Borrowed("MOV    M,B"), 1),
+		0x71 => (Cow::Borrowed("MOV    M,C"), 1),
+		0x72 => (Cow::Borrowed("MOV    M,D"), 1),
+		0x73 => (Cow::Borrowed("MOV    M.E"), 1),
+		0x74 => (Cow::Borrowed("MOV    M,H"), 1),
+		0x75 => (Cow::Borrowed("MOV    M,L"), 1),
+		0x76 => (Cow::Borrowed("HLT"), 1),
+		0x77 => (Cow::Borrowed("MOV    M,A"), 1),
+		0x78 => (Cow::Borrowed("MOV    A,B"), 1),
+		0x79 => (Cow::Borrowed("MOV    A,C"), 1),
+		0x7a => (Cow::Borrowed("MOV    A,D"), 1),
+		0x7b => (Cow::Borrowed("MOV    A,E"), 1),
+		0x7c => (Cow::Borrowed("MOV    A,H"), 1),
+		0x7d => (Cow::Borrowed("MOV    A,L"), 1),
+		0x7e => (Cow::Borrowed("MOV    A,M"), 1),
+		0x7f => (Cow::Borrowed("MOV    A,A"), 1),
             
-		0x80 => {print!("ADD    B"); 1},
-		0x81 => {print!("ADD    C"); 1},
-		0x82 => {print!("ADD    D"); 1},
-		0x83 => {print!("ADD    E"); 1},
-		0x84 => {print!("ADD    H"); 1},
-		0x85 => {print!("ADD    L"); 1},
-		0x86 => {print!("ADD    M"); 1},
-		0x87 => {print!("ADD    A"); 1},
-		0x88 => {print!("ADC    B"); 1},
-		0x89 => {print!("ADC    C"); 1},
-		0x8a => {print!("ADC    D"); 1},
-		0x8b => {print!("ADC    E"); 1},
-		0x8c => {print!("ADC    H"); 1},
-		0x8d => {print!("ADC    L"); 1},
-		0x8e => {print!("ADC    M"); 1},
-		0x8f => {print!("ADC    A"); 1},
+		0x80 => (Cow::Borrowed("ADD    B"), 1),
+		0x81 => (Cow::Borrowed("ADD    C"), 1),
+		0x82 => (Cow::Borrowed("ADD    D"), 1),
+		0x83 => (Cow::Borrowed("ADD    E"), 1),
+		0x84 => (Cow::Borrowed("ADD    H"), 1),
+		0x85 => (Cow::Borrowed("ADD    L"), 1),
+		0x86 => (Cow::Borrowed("ADD    M"), 1),
+		0x87 => (Cow::Borrowed("ADD    A"), 1),
+		0x88 => (Cow::Borrowed("ADC    B"), 1),
+		0x89 => (Cow::Borrowed("ADC    C"), 1),
+		0x8a => (Cow::Borrowed("ADC    D"), 1),
+		0x8b => (Cow::Borrowed("ADC    E"), 1),
+		0x8c => (Cow::Borrowed("ADC    H"), 1),
+		0x8d => (Cow::Borrowed("ADC    L"), 1),
+		0x8e => (Cow::Borrowed("ADC    M"), 1),
+		0x8f => (Cow::Borrowed("ADC    A"), 1),
             
-		0x90 => {print!("SUB    B"); 1},
-		0x91 => {print!("SUB    C"); 1},
-		0x92 => {print!("SUB    D"); 1},
-		0x93 => {print!("SUB    E"); 1},
-		0x94 => {print!("SUB    H"); 1},
-		0x95 => {print!("SUB    L"); 1},
-		0x96 => {print!("SUB    M"); 1},
-		0x97 => {print!("SUB    A"); 1},
-		0x98 => {print!("SBB    B"); 1},
-		0x99 => {print!("SBB    C"); 1},
-		0x9a => {print!("SBB    D"); 1},
-		0x9b => {print!("SBB    E"); 1},
-		0x9c => {print!("SBB    H"); 1},
-		0x9d => {print!("SBB    L"); 1},
-		0x9e => {print!("SBB    M"); 1},
-		0x9f => {print!("SBB    A"); 1},
+		0x90 => (Cow::Borrowed("SUB    B"), 1),
+		0x91 => (Cow::Borrowed("SUB    C"), 1),
+		0x92 => (Cow::Borrowed("SUB    D"), 1),
+		0x93 => (Cow::Borrowed("SUB    E"), 1),
+		0x94 => (Cow::Borrowed("SUB    H"), 1),
+		0x95 => (Cow::Borrowed("SUB    L"), 1),
+		0x96 => (Cow::Borrowed("SUB    M"), 1),
+		0x97 => (Cow::Borrowed("SUB    A"), 1),
+		0x98 => (Cow::Borrowed("SBB    B"), 1),
+		0x99 => (Cow::Borrowed("SBB    C"), 1),
+		0x9a => (Cow::Borrowed("SBB    D"), 1),
+		0x9b => (Cow::Borrowed("SBB    E"), 1),
+		0x9c => (Cow::Borrowed("SBB    H"), 1),
+		0x9d => (Cow::Borrowed("SBB    L"), 1),
+		0x9e => (Cow::Borrowed("SBB    M"), 1),
+		0x9f => (Cow::Borrowed("SBB    A"), 1),
             
-		0xa0 => {print!("ANA    B"); 1},
-		0xa1 => {print!("ANA    C"); 1},
-		0xa2 => {print!("ANA    D"); 1},
-		0xa3 => {print!("ANA    E"); 1},
-		0xa4 => {print!("ANA    H"); 1},
-		0xa5 => {print!("ANA    L"); 1},
-		0xa6 => {print!("ANA    M"); 1},
-		0xa7 => {print!("ANA    A"); 1},
-		0xa8 => {print!("XRA    B"); 1},
-		0xa9 => {print!("XRA    C"); 1},
-		0xaa => {print!("XRA    D"); 1},
-		0xab => {print!("XRA    E"); 1},
-		0xac => {print!("XRA    H"); 1},
-		0xad => {print!("XRA    L"); 1},
-		0xae => {print!("XRA    M"); 1},
-		0xaf => {print!("XRA    A"); 1},
+		0xa0 => (Cow::Borrowed("ANA    B"), 1),
+		0xa1 => (Cow::Borrowed("ANA    C"), 1),
+		0xa2 => (Cow::Borrowed("ANA    D"), 1),
+		0xa3 => (Cow::Borrowed("ANA    E"), 1),
+		0xa4 => (Cow::Borrowed("ANA    H"), 1),
+		0xa5 => (Cow::Borrowed("ANA    L"), 1),
+		0xa6 => (Cow::Borrowed("ANA    M"), 1),
+		0xa7 => (Cow::Borrowed("ANA    A"), 1),
+		0xa8 => (Cow::Borrowed("XRA    B"), 1),
+		0xa9 => (Cow::Borrowed("XRA    C"), 1),
+		0xaa => (Cow::Borrowed("XRA    D"), 1),
+		0xab => (Cow::Borrowed("XRA    E"), 1),
+		0xac => (Cow::Borrowed("XRA    H"), 1),
+		0xad => (Cow::Borrowed("XRA    L"), 1),
+		0xae => (Cow::Borrowed("XRA    M"), 1),
+		0xaf => (Cow::Borrowed("XRA    A"), 1),
             
-		0xb0 => {print!("ORA    B"); 1},
-		0xb1 => {print!("ORA    C"); 1},
-		0xb2 => {print!("ORA    D"); 1},
-		0xb3 => {print!("ORA    E"); 1},
-		0xb4 => {print!("ORA    H"); 1},
-		0xb5 => {print!("ORA    L"); 1},
-		0xb6 => {print!("ORA    M"); 1},
-		0xb7 => {print!("ORA    A"); 1},
-		0xb8 => {print!("CMP    B"); 1},
-		0xb9 => {print!("CMP    C"); 1},
-		0xba => {print!("CMP    D"); 1},
-		0xbb => {print!("CMP    E"); 1},
-		0xbc => {print!("CMP    H"); 1},
-		0xbd => {print!("CMP    L"); 1},
-		0xbe => {print!("CMP    M"); 1},
-		0xbf => {print!("CMP    A"); 1},
+		0xb0 => (Cow::Borrowed("ORA    B"), 1),
+		0xb1 => (Cow::Borrowed("ORA    C"), 1),
+		0xb2 => (Cow::Borrowed("ORA    D"), 1),
+		0xb3 => (Cow::Borrowed("ORA    E"), 1),
+		0xb4 => (Cow::Borrowed("ORA    H"), 1),
+		0xb5 => (Cow::Borrowed("ORA    L"), 1),
+		0xb6 => (Cow::Borrowed("ORA    M"), 1),
+		0xb7 => (Cow::Borrowed("ORA    A"), 1),
+		0xb8 => (Cow::Borrowed("CMP    B"), 1),
+		0xb9 => (Cow::Borrowed("CMP    C"), 1),
+		0xba => (Cow::Borrowed("CMP    D"), 1),
+		0xbb => (Cow::Borrowed("CMP    E"), 1),
+		0xbc => (Cow::Borrowed("CMP    H"), 1),
+		0xbd => (Cow::Borrowed("CMP    L"), 1),
+		0xbe => (Cow::Borrowed("CMP    M"), 1),
+		0xbf => (Cow::Borrowed("CMP    A"), 1),
             
-		0xc0 => {print!("RNZ"); 1},
-		0xc1 => {print!("POP    B"); 1},
-		0xc2 => {print!("JNZ    ${:02X}{:02X}", arg2, arg1); 3},
-		0xc3 => {print!("JMP    ${:02X}{:02X}", arg2, arg1); 3},
-		0xc4 => {print!("CNZ    ${:02X}{:02X}", arg2, arg1); 3},
-		0xc5 => {print!("PUSH   B"); 1},
-		0xc6 => {print!("ADI    #${:02X}", arg1); 2},
-		0xc7 => {print!("RST    0"); 1},
-		0xc8 => {print!("RZ"); 1},
-		0xc9 => {print!("RET"); 1},
-		0xca => {print!("JZ     ${:02X}{:02X}", arg2, arg1); 3},
-		0xcb => {print!("JMP    ${:02X}{:02X}", arg2, arg1); 3},
-		0xcc => {print!("CZ     ${:02X}{:02X}", arg2, arg1); 3},
-		0xcd => {print!("CALL   ${:02X}{:02X}", arg2, arg1); 3},
-		0xce => {print!("ACI    #${:02X}", arg1); 2},
-		0xcf => {print!("RST    1"); 1},
+		0xc0 => (Cow::Borrowed("RNZ"), 1),
+		0xc1 => (Cow::Borrowed("POP    B"), 1),
+		0xc2 => (Cow::Owned(format!("JNZ    ${:02X}{:02X}", arg2, arg1)), 3),
+		0xc3 => (Cow::Owned(format!("JMP    ${:02X}{:02X}", arg2, arg1)), 3),
+		0xc4 => (Cow::Owned(format!("CNZ    ${:02X}{:02X}", arg2, arg1)), 3),
+		0xc5 => (Cow::Borrowed("PUSH   B"), 1),
+		0xc6 => (Cow::Owned(format!("ADI    #${:02X}", arg1)), 2),
+		0xc7 => (Cow::Borrowed("RST    0"), 1),
+		0xc8 => (Cow::Borrowed("RZ"), 1),
+		0xc9 => (Cow::Borrowed("RET"), 1),
+		0xca => (Cow::Owned(format!("JZ     ${:02X}{:02X}", arg2, arg1)), 3),
+		0xcb => (Cow::Owned(format!("JMP    ${:02X}{:02X}", arg2, arg1)), 3),
+		0xcc => (Cow::Owned(format!("CZ     ${:02X}{:02X}", arg2, arg1)), 3),
+		0xcd => (Cow::Owned(format!("CALL   ${:02X}{:02X}", arg2, arg1)), 3),
+		0xce => (Cow::Owned(format!("ACI    #${:02X}", arg1)), 2),
+		0xcf => (Cow::Borrowed("RST    1"), 1),
             
-		0xd0 => {print!("RNC"); 1},
-		0xd1 => {print!("POP    D"); 1},
-		0xd2 => {print!("JNC    ${:02X}{:02X}", arg2, arg1); 3},
-		0xd3 => {print!("OUT    #${:02X}", arg1); 2},
-		0xd4 => {print!("CNC    ${:02X}{:02X}", arg2, arg1); 3},
-		0xd5 => {print!("PUSH   D"); 1},
-		0xd6 => {print!("SUI    #${:02X}", arg1); 2},
-		0xd7 => {print!("RST    2"); 1},
-		0xd8 => {print!("RC");  1},
-		0xd9 => {print!("RET"); 1},
-		0xda => {print!("JC     ${:02X}{:02X}", arg2, arg1); 3},
-		0xdb => {print!("IN     #${:02X}", arg1); 2},
-		0xdc => {print!("CC     ${:02X}{:02X}", arg2, arg1); 3},
-		0xdd => {print!("CALL   ${:02X}{:02X}", arg2, arg1); 3},
-		0xde => {print!("SBI    #${:02X}", arg1); 2},
-		0xdf => {print!("RST    3"); 1},
+		0xd0 => (Cow::Borrowed("RNC"), 1),
+		0xd1 => (Cow::Borrowed("POP    D"), 1),
+		0xd2 => (Cow::Owned(format!("JNC    ${:02X}{:02X}", arg2, arg1)), 3),
+		0xd3 => (Cow::Owned(format!("OUT    #${:02X}", arg1)), 2),
+		0xd4 => (Cow::Owned(format!("CNC    ${:02X}{:02X}", arg2, arg1)), 3),
+		0xd5 => (Cow::Borrowed("PUSH   D"), 1),
+		0xd6 => (Cow::Owned(format!("SUI    #${:02X}", arg1)), 2),
+		0xd7 => (Cow::Borrowed("RST    2"), 1),
+		0xd8 => (Cow::Borrowed("RC"), 1),
+		0xd9 => (Cow::Borrowed("RET"), 1),
+		0xda => (Cow::Owned(format!("JC     ${:02X}{:02X}", arg2, arg1)), 3),
+		0xdb => (Cow::Owned(format!("IN     #${:02X}", arg1)), 2),
+		0xdc => (Cow::Owned(format!("CC     ${:02X}{:02X}", arg2, arg1)), 3),
+		0xdd => (Cow::Owned(format!("CALL   ${:02X}{:02X}", arg2, arg1)), 3),
+		0xde => (Cow::Owned(format!("SBI    #${:02X}", arg1)), 2),
+		0xdf => (Cow::Borrowed("RST    3"), 1),
             
-		0xe0 => {print!("RPO"); 1},
-		0xe1 => {print!("POP    H"); 1},
-		0xe2 => {print!("JPO    ${:02X}{:02X}", arg2, arg1); 3},
-		0xe3 => {print!("XTHL");1},
-		0xe4 => {print!("CPO    ${:02X}{:02X}", arg2, arg1); 3},
-		0xe5 => {print!("PUSH   H"); 1},
-		0xe6 => {print!("ANI    #${:02X}", arg1); 2},
-		0xe7 => {print!("RST    4"); 1},
-		0xe8 => {print!("RPE"); 1},
-		0xe9 => {print!("PCHL");1},
-		0xea => {print!("JPE    ${:02X}{:02X}", arg2, arg1); 3},
-		0xeb => {print!("XCHG"); 1},
-		0xec => {print!("CPE     ${:02X}{:02X}", arg2, arg1); 3},
-		0xed => {print!("CALL   ${:02X}{:02X}", arg2, arg1); 3},
-		0xee => {print!("XRI    #${:02X}", arg1); 2},
-		0xef => {print!("RST    5"); 1},
+		0xe0 => (Cow::Borrowed("RPO"), 1),
+		0xe1 => (Cow::Borrowed("POP    H"), 1),
+		0xe2 => (Cow::Owned(format!("JPO    ${:02X}{:02X}", arg2, arg1)), 3),
+		0xe3 => (Cow::Borrowed("XTHL"), 1),
+		0xe4 => (Cow::Owned(format!("CPO    ${:02X}{:02X}", arg2, arg1)), 3),
+		0xe5 => (Cow::Borrowed("PUSH   H"), 1),
+		0xe6 => (Cow::Owned(format!("ANI    #${:02X}", arg1)), 2),
+		0xe7 => (Cow::Borrowed("RST    4"), 1),
+		0xe8 => (Cow::Borrowed("RPE"), 1),
+		0xe9 => (Cow::Borrowed("PCHL"), 1),
+		0xea => (Cow::Owned(format!("JPE    ${:02X}{:02X}", arg2, arg1)), 3),
+		0xeb => (Cow::Borrowed("XCHG"), 1),
+		0xec => (Cow::Owned(format!("CPE     ${:02X}{:02X}", arg2, arg1)), 3),
+		0xed => (Cow::Owned(format!("CALL   ${:02X}{:02X}", arg2, arg1)), 3),
+		0xee => (Cow::Owned(format!("XRI    #${:02X}", arg1)), 2),
+		0xef => (Cow::Borrowed("RST    5"), 1),
             
-		0xf0 => {print!("RP");  1},
-		0xf1 => {print!("POP    PSW"); 1},
-		0xf2 => {print!("JP     ${:02X}{:02X}", arg2, arg1); 3},
-		0xf3 => {print!("DI");  1},
-		0xf4 => {print!("CP     ${:02X}{:02X}", arg2, arg1); 3},
-		0xf5 => {print!("PUSH   PSW"); 1},
-		0xf6 => {print!("ORI    #${:02X}", arg1); 2},
-		0xf7 => {print!("RST    6"); 1},
-		0xf8 => {print!("RM");  1},
-		0xf9 => {print!("SPHL");1},
-		0xfa => {print!("JM     ${:02X}{:02X}", arg2, arg1); 3},
-		0xfb => {print!("EI");  1},
-		0xfc => {print!("CM     ${:02X}{:02X}", arg2, arg1); 3},
-		0xfd => {print!("CALL   ${:02X}{:02X}", arg2, arg1); 3},
-		0xfe => {print!("CPI    #${:02X}", arg1); 2},
-		0xff => {print!("RST    7"); 1},
-		_ => unreachable!()
-	};
-	opbytes
+		0xf0 => (Cow::Borrowed("RP"), 1),
+		0xf1 => (Cow::Borrowed("POP    PSW"), 1),
+		0xf2 => (Cow::Owned(format!("JP     ${:02X}{:02X}", arg2, arg1)), 3),
+		0xf3 => (Cow::Borrowed("DI"), 1),
+		0xf4 => (Cow::Owned(format!("CP     ${:02X}{:02X}", arg2, arg1)), 3),
+		0xf5 => (Cow::Borrowed("PUSH   PSW"), 1),
+		0xf6 => (Cow::Owned(format!("ORI    #${:02X}", arg1)), 2),
+		0xf7 => (Cow::Borrowed("RST    6"), 1),
+		0xf8 => (Cow::Borrowed("RM"), 1),
+		0xf9 => (Cow::Borrowed("SPHL"), 1),
+		0xfa => (Cow::Owned(format!("JM     ${:02X}{:02X}", arg2, arg1)), 3),
+		0xfb => (Cow::Borrowed("EI"), 1),
+		0xfc => (Cow::Owned(format!("CM     ${:02X}{:02X}", arg2, arg1)), 3),
+		0xfd => (Cow::Owned(format!("CALL   ${:02X}{:02X}", arg2, arg1)), 3),
+		0xfe => (Cow::Owned(format!("CPI    #${:02X}", arg1)), 2),
+		0xff => (Cow::Borrowed("RST    7"), 1)
+	}
+}
+
+// length in bytes of the instruction, without decoding the mnemonic
+pub fn instr_len(opcode: u8) -> u8 {
+	match opcode {
+		0x01 | 0x11 | 0x21 | 0x31 | 0x22 | 0x2a | 0x32 | 0x3a => 3,
+		0xc2 | 0xc3 | 0xc4 | 0xca | 0xcb | 0xcc | 0xcd => 3,
+		0xd2 | 0xd4 | 0xda | 0xdc | 0xdd => 3,
+		0xe2 | 0xe4 | 0xea | 0xec | 0xed => 3,
+		0xf2 | 0xf4 | 0xfa | 0xfc | 0xfd => 3,
+		0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e => 2,
+		0xc6 | 0xce | 0xd3 | 0xd6 | 0xdb | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe => 2,
+		_ => 1
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn instr_len_matches_disassembly() {
+		for opcode in 0..=0xFF {
+			assert_eq!(instr_len(opcode), disassemble(opcode, 0, 0).1, "opcode {:02X}", opcode);
+		}
+	}
 }
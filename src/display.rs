@@ -0,0 +1,208 @@
+use image;
+use image::RgbaImage;
+use toml;
+
+use {WIDTH, HEIGHT};
+
+pub type Color = [u8; 4];
+
+const BLACK: Color = [0, 0, 0, 255];
+const WHITE: Color = [255; 4];
+
+// Area of the screen tinted like the coloured cellophane on the cabinet.
+// Coordinates are in screen pixels of the upright monitor, origin top left.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlayRect {
+	pub x: u32,
+	pub y: u32,
+	pub w: u32,
+	pub h: u32,
+	pub color: Color
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Overlay {
+	// later rectangles are drawn over earlier ones
+	Rects(Vec<OverlayRect>),
+	// full-size PNG, lit pixels take its colour
+	Image(String)
+}
+
+impl Overlay {
+	pub fn builtin(name: &str) -> Option<Overlay> {
+		match name {
+			"mono" => Some(Overlay::Rects(Vec::new())),
+			"invaders" => Some(Overlay::Rects(vec![
+				// red in UFO-area
+				OverlayRect {x: 0, y: 35, w: WIDTH, h: 21, color: [255, 0, 0, 255]},
+				// green in bottom area, the lowest line only below the remaining bases
+				OverlayRect {x: 0, y: 192, w: WIDTH, h: 49, color: [0, 255, 0, 255]},
+				OverlayRect {x: 20, y: 241, w: 101, h: 15, color: [0, 255, 0, 255]}
+			])),
+			_ => None
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayConfig {
+	pub overlay: Overlay,
+	// colour of lit pixels outside the overlay
+	pub foreground: Color,
+	// colour of unlit pixels
+	pub background: Color
+}
+
+impl DisplayConfig {
+	pub fn new(overlay: Overlay) -> DisplayConfig {
+		DisplayConfig {
+			overlay,
+			foreground: WHITE,
+			background: BLACK
+		}
+	}
+
+	// `overlay` is a built-in name or a PNG file, `rects` a list of overlay rectangles
+	pub fn from_table(table: &toml::value::Table, base: &DisplayConfig) -> DisplayConfig {
+		let mut config = base.clone();
+
+		if let Some(name) = table.get("overlay").and_then(|v| v.as_str()) {
+			config.overlay = Overlay::builtin(name).unwrap_or(Overlay::Image(name.to_string()));
+		}
+		if let Some(rects) = table.get("rects").and_then(|v| v.as_array()) {
+			config.overlay = Overlay::Rects(rects.iter().filter_map(parse_rect).collect());
+		}
+		if let Some(color) = table.get("foreground").and_then(parse_color) {
+			config.foreground = color;
+		}
+		if let Some(color) = table.get("background").and_then(parse_color) {
+			config.background = color;
+		}
+		config
+	}
+
+	pub fn to_table(&self) -> toml::value::Table {
+		let mut table = toml::value::Table::new();
+		match self.overlay {
+			Overlay::Rects(ref rects) => {
+				let rects = rects.iter().map(|r| {
+					let mut rect = toml::value::Table::new();
+					rect.insert("x".to_string(), toml::Value::Integer(r.x as i64));
+					rect.insert("y".to_string(), toml::Value::Integer(r.y as i64));
+					rect.insert("w".to_string(), toml::Value::Integer(r.w as i64));
+					rect.insert("h".to_string(), toml::Value::Integer(r.h as i64));
+					rect.insert("color".to_string(), color_value(r.color));
+					toml::Value::Table(rect)
+				}).collect();
+				table.insert("rects".to_string(), toml::Value::Array(rects));
+			},
+			Overlay::Image(ref file) => {
+				table.insert("overlay".to_string(), toml::Value::String(file.clone()));
+			}
+		}
+		table.insert("foreground".to_string(), color_value(self.foreground));
+		table.insert("background".to_string(), color_value(self.background));
+		table
+	}
+}
+
+// [r, g, b] or [r, g, b, a]
+fn parse_color(value: &toml::Value) -> Option<Color> {
+	let values: Vec<u8> = value.as_array()?.iter()
+		.filter_map(|v| v.as_integer())
+		.map(|v| v.max(0).min(255) as u8)
+		.collect();
+	match values.len() {
+		3 => Some([values[0], values[1], values[2], 255]),
+		4 => Some([values[0], values[1], values[2], values[3]]),
+		_ => None
+	}
+}
+
+fn color_value(color: Color) -> toml::Value {
+	toml::Value::Array(color.iter().map(|&c| toml::Value::Integer(c as i64)).collect())
+}
+
+fn parse_rect(value: &toml::Value) -> Option<OverlayRect> {
+	let get = |key: &str| value.get(key).and_then(|v| v.as_integer()).map(|v| v.max(0) as u32);
+	Some(OverlayRect {
+		x: get("x")?,
+		y: get("y")?,
+		w: get("w")?,
+		h: get("h")?,
+		color: value.get("color").and_then(parse_color)?
+	})
+}
+
+
+// Turns the 1 bit framebuffer into colours, using a precomputed colour per pixel.
+#[derive(Debug)]
+pub struct Screen {
+	colors: Vec<Color>,
+	background: Color
+}
+
+impl Screen {
+	pub fn new(config: &DisplayConfig) -> Screen {
+		let mut colors = vec![config.foreground; (WIDTH * HEIGHT) as usize];
+
+		match config.overlay {
+			Overlay::Rects(ref rects) => {
+				for r in rects {
+					for y in r.y..r.y.saturating_add(r.h).min(HEIGHT) {
+						for x in r.x..r.x.saturating_add(r.w).min(WIDTH) {
+							colors[index(x, y)] = r.color;
+						}
+					}
+				}
+			},
+			Overlay::Image(ref file) => {
+				match image::open(file) {
+					Ok(img) => {
+						let img = image::imageops::resize(&img.to_rgba(), WIDTH, HEIGHT, image::FilterType::Nearest);
+						for (x, y, pixel) in img.enumerate_pixels() {
+							colors[index(x, y)] = pixel.data;
+						}
+					},
+					Err(e) => println!("=> Can't load overlay {}: {}", file, e)
+				}
+			}
+		}
+
+		Screen {
+			colors,
+			background: config.background
+		}
+	}
+
+	// The framebuffer is stored column by column from the bottom, the image is upright.
+	// Flipped turns it around, the overlay stays in place like the cellophane on the glass.
+	pub fn render(&self, framebuffer: &[u8], flipped: bool, img_buffer: &mut RgbaImage) {
+		for (n, byte) in framebuffer.iter().enumerate() {
+			let i = n as u32 * 8;
+
+			let x = i / HEIGHT;
+			for shift in 0..8 {
+				let mut x = x;
+				let mut y = HEIGHT - 1 - ((i % HEIGHT) + shift as u32);
+				if flipped {
+					x = WIDTH - 1 - x;
+					y = HEIGHT - 1 - y;
+				}
+
+				let color = if (byte >> shift) & 1 == 0 {
+					self.background
+				}
+				else {
+					self.colors[index(x, y)]
+				};
+
+				img_buffer.put_pixel(x, y, image::Rgba(color));
+			}
+		}
+	}
+}
+
+fn index(x: u32, y: u32) -> usize {
+	(y * WIDTH + x) as usize
+}
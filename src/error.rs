@@ -0,0 +1,28 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// Problems of the emulated machine. None of them stop the host process.
+#[derive(Debug)]
+pub enum EmuError {
+	// the ROM file can't be read
+	RomUnreadable(String, io::Error),
+	// the ROM doesn't fit into the 64K address space at the given offset
+	RomTooLarge { size: usize, offset: usize },
+	// a push or pop wrapped the stack pointer around the end of memory
+	StackWrap { pc: u16, sp: u16 }
+}
+
+impl fmt::Display for EmuError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			EmuError::RomUnreadable(ref file, ref e) => write!(f, "Can't read ROM file {}: {}", file, e),
+			EmuError::RomTooLarge {size, offset} =>
+				write!(f, "ROM of {} bytes doesn't fit into memory at {:#06X}", size, offset),
+			EmuError::StackWrap {pc, sp} =>
+				write!(f, "Stack pointer wrapped around to {:04X} at {:04X}", sp, pc)
+		}
+	}
+}
+
+impl Error for EmuError {}
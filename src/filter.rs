@@ -0,0 +1,217 @@
+use image::{RgbaImage, Rgba};
+use toml;
+
+// output pixels per screen pixel when scanlines or curvature are on
+const UPSCALE: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterConfig {
+	// blend in the previous frames like the slowly fading phosphor
+	pub phosphor: bool,
+	// how much of the previous frame remains, 0 to 1
+	pub persistence: f32,
+	pub scanlines: bool,
+	pub bloom: bool,
+	pub curvature: bool
+}
+
+impl FilterConfig {
+	pub fn new() -> FilterConfig {
+		FilterConfig {
+			phosphor: false,
+			persistence: 0.6,
+			scanlines: false,
+			bloom: false,
+			curvature: false
+		}
+	}
+
+	pub fn from_table(table: &toml::value::Table) -> FilterConfig {
+		let mut config = FilterConfig::new();
+		let flag = |key: &str, default: bool| table.get(key).and_then(|v| v.as_bool()).unwrap_or(default);
+
+		config.phosphor = flag("phosphor", config.phosphor);
+		config.scanlines = flag("scanlines", config.scanlines);
+		config.bloom = flag("bloom", config.bloom);
+		config.curvature = flag("curvature", config.curvature);
+		if let Some(persistence) = table.get("persistence").and_then(|v| v.as_float()) {
+			config.persistence = persistence.max(0.0).min(1.0) as f32;
+		}
+		config
+	}
+
+	pub fn to_table(&self) -> toml::value::Table {
+		let mut table = toml::value::Table::new();
+		table.insert("phosphor".to_string(), toml::Value::Boolean(self.phosphor));
+		table.insert("persistence".to_string(), toml::Value::Float(self.persistence as f64));
+		table.insert("scanlines".to_string(), toml::Value::Boolean(self.scanlines));
+		table.insert("bloom".to_string(), toml::Value::Boolean(self.bloom));
+		table.insert("curvature".to_string(), toml::Value::Boolean(self.curvature));
+		table
+	}
+
+	pub fn any(&self) -> bool {
+		self.phosphor || self.scanlines || self.bloom || self.curvature
+	}
+}
+
+
+// CPU side post-processing of the rendered screen, so it also works without a window.
+#[derive(Debug)]
+pub struct Filters {
+	pub config: FilterConfig,
+	// last phosphor state per channel
+	previous: Vec<f32>,
+	frame: RgbaImage,
+	output: RgbaImage
+}
+
+impl Filters {
+	pub fn new(config: &FilterConfig) -> Filters {
+		Filters {
+			config: config.clone(),
+			previous: Vec::new(),
+			frame: RgbaImage::new(1, 1),
+			output: RgbaImage::new(1, 1)
+		}
+	}
+
+	pub fn apply<'a>(&'a mut self, input: &'a RgbaImage) -> &'a RgbaImage {
+		if !self.config.any() {
+			self.previous.clear();
+			return input;
+		}
+
+		self.frame = input.clone();
+		if self.config.phosphor {
+			self.phosphor();
+		}
+		else {
+			self.previous.clear();
+		}
+		if self.config.bloom {
+			self.bloom();
+		}
+		if !self.config.scanlines && !self.config.curvature {
+			return &self.frame;
+		}
+
+		self.upscale();
+		if self.config.scanlines {
+			self.scanlines();
+		}
+		if self.config.curvature {
+			self.curvature();
+		}
+		&self.output
+	}
+
+	fn phosphor(&mut self) {
+		let len = (self.frame.width() * self.frame.height() * 3) as usize;
+		if self.previous.len() != len {
+			self.previous = vec![0.0; len];
+		}
+
+		let decay = self.config.persistence;
+		for (i, pixel) in self.frame.pixels_mut().enumerate() {
+			for c in 0..3 {
+				let v = (pixel.data[c] as f32).max(self.previous[i * 3 + c] * decay);
+				self.previous[i * 3 + c] = v;
+				pixel.data[c] = v as u8;
+			}
+		}
+	}
+
+	// adds a blurred copy so lit pixels glow into their neighbours
+	fn bloom(&mut self) {
+		let (w, h) = self.frame.dimensions();
+		let radius = 2i32;
+		let mut glow = vec![[0u32; 3]; (w * h) as usize];
+
+		// separable box blur: horizontal, then vertical
+		for y in 0..h {
+			for x in 0..w {
+				let mut sum = [0u32; 3];
+				for dx in -radius..radius + 1 {
+					let sx = x as i32 + dx;
+					if sx >= 0 && sx < w as i32 {
+						let p = self.frame.get_pixel(sx as u32, y);
+						for c in 0..3 { sum[c] += p.data[c] as u32; }
+					}
+				}
+				glow[(y * w + x) as usize] = sum;
+			}
+		}
+
+		let n = (radius * 2 + 1) as u32;
+		for x in 0..w {
+			for y in 0..h {
+				let mut sum = [0u32; 3];
+				for dy in -radius..radius + 1 {
+					let sy = y as i32 + dy;
+					if sy >= 0 && sy < h as i32 {
+						let g = glow[(sy as u32 * w + x) as usize];
+						for c in 0..3 { sum[c] += g[c]; }
+					}
+				}
+				let pixel = self.frame.get_pixel_mut(x, y);
+				for c in 0..3 {
+					let add = sum[c] / (n * n) / 2;
+					pixel.data[c] = (pixel.data[c] as u32 + add).min(255) as u8;
+				}
+			}
+		}
+	}
+
+	fn upscale(&mut self) {
+		let (w, h) = self.frame.dimensions();
+		if self.output.dimensions() != (w * UPSCALE, h * UPSCALE) {
+			self.output = RgbaImage::new(w * UPSCALE, h * UPSCALE);
+		}
+		for (x, y, pixel) in self.output.enumerate_pixels_mut() {
+			*pixel = *self.frame.get_pixel(x / UPSCALE, y / UPSCALE);
+		}
+	}
+
+	// The monitor is mounted sideways, so its scanlines are columns of the upright picture.
+	fn scanlines(&mut self) {
+		for (x, _, pixel) in self.output.enumerate_pixels_mut() {
+			if x % UPSCALE == UPSCALE - 1 {
+				for c in 0..3 {
+					pixel.data[c] /= 2;
+				}
+			}
+		}
+	}
+
+	// barrel distortion with darker corners
+	fn curvature(&mut self) {
+		let source = self.output.clone();
+		let (w, h) = source.dimensions();
+		let k = 0.08;
+
+		for (x, y, pixel) in self.output.enumerate_pixels_mut() {
+			let u = x as f32 / w as f32 * 2.0 - 1.0;
+			let v = y as f32 / h as f32 * 2.0 - 1.0;
+			let r2 = u * u + v * v;
+			let su = u * (1.0 + k * r2);
+			let sv = v * (1.0 + k * r2);
+
+			*pixel = if su.abs() >= 1.0 || sv.abs() >= 1.0 {
+				Rgba([0, 0, 0, 255])
+			}
+			else {
+				let sx = ((su + 1.0) / 2.0 * w as f32) as u32;
+				let sy = ((sv + 1.0) / 2.0 * h as f32) as u32;
+				let p = source.get_pixel(sx.min(w - 1), sy.min(h - 1));
+				let shade = 1.0 - 0.25 * r2 / 2.0;
+				Rgba([
+					(p.data[0] as f32 * shade) as u8,
+					(p.data[1] as f32 * shade) as u8,
+					(p.data[2] as f32 * shade) as u8,
+					255
+				])
+			};
+		}
+	}
+}
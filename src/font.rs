@@ -0,0 +1,155 @@
+use image::{RgbaImage, Rgba};
+
+// Size of a character cell in pixels: 5x7 glyphs with a gap to the right and below.
+pub const CHAR_W: u32 = 6;
+pub const CHAR_H: u32 = 8;
+
+// colours of the overlays drawn over the game
+pub const BACKGROUND: Rgba<u8> = Rgba { data: [0, 0, 0, 0xD0] };
+pub const TEXT: Rgba<u8> = Rgba { data: [0xC0, 0xC0, 0xC0, 0xFF] };
+pub const LABEL: Rgba<u8> = Rgba { data: [0x60, 0xA0, 0xFF, 0xFF] };
+
+// fills the image with the overlay background
+pub fn clear(img: &mut RgbaImage) {
+	for pixel in img.pixels_mut() {
+		*pixel = BACKGROUND;
+	}
+}
+
+// Draws text at a position counted in character cells, clipped to the image.
+// Characters outside of printable ASCII are shown as '.'. The background is
+// left as it is unless given.
+pub fn draw_text(img: &mut RgbaImage, col: u32, row: u32, text: &str, fg: Rgba<u8>, bg: Option<Rgba<u8>>) {
+	let (w, h) = img.dimensions();
+	for (i, c) in text.chars().enumerate() {
+		let x0 = (col + i as u32) * CHAR_W;
+		let y0 = row * CHAR_H;
+		if x0 >= w || y0 >= h {
+			break;
+		}
+		let glyph = &GLYPHS[glyph_index(c)];
+		for y in 0..CHAR_H {
+			for x in 0..CHAR_W {
+				let (px, py) = (x0 + x, y0 + y);
+				if px >= w || py >= h {
+					continue;
+				}
+				let set = x < 5 && y < 7 && (glyph[y as usize] >> (4 - x)) & 1 == 1;
+				if set {
+					img.put_pixel(px, py, fg);
+				}
+				else if let Some(bg) = bg {
+					img.put_pixel(px, py, bg);
+				}
+			}
+		}
+	}
+}
+
+// printable ASCII, everything else as '.'
+pub fn printable(c: char) -> char {
+	if c >= ' ' && c <= '~' { c } else { '.' }
+}
+
+fn glyph_index(c: char) -> usize {
+	printable(c) as usize - 0x20
+}
+
+// rows from the top, bit 4 is the leftmost pixel
+static GLYPHS: [[u8; 7]; 95] = [
+	[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],	// ' '
+	[0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],	// '!'
+	[0x0A, 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00],	// '"'
+	[0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],	// '#'
+	[0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04],	// '$'
+	[0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],	// '%'
+	[0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D],	// '&'
+	[0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],	// "'"
+	[0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],	// '('
+	[0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],	// ')'
+	[0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00],	// '*'
+	[0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],	// '+'
+	[0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],	// ','
+	[0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],	// '-'
+	[0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],	// '.'
+	[0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],	// '/'
+	[0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],	// '0'
+	[0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],	// '1'
+	[0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],	// '2'
+	[0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],	// '3'
+	[0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],	// '4'
+	[0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],	// '5'
+	[0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],	// '6'
+	[0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],	// '7'
+	[0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],	// '8'
+	[0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],	// '9'
+	[0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],	// ':'
+	[0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08],	// ';'
+	[0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02],	// '<'
+	[0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],	// '='
+	[0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08],	// '>'
+	[0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],	// '?'
+	[0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E],	// '@'
+	[0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],	// 'A'
+	[0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],	// 'B'
+	[0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],	// 'C'
+	[0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],	// 'D'
+	[0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],	// 'E'
+	[0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],	// 'F'
+	[0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],	// 'G'
+	[0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],	// 'H'
+	[0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],	// 'I'
+	[0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],	// 'J'
+	[0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],	// 'K'
+	[0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],	// 'L'
+	[0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],	// 'M'
+	[0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],	// 'N'
+	[0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],	// 'O'
+	[0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],	// 'P'
+	[0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],	// 'Q'
+	[0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],	// 'R'
+	[0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],	// 'S'
+	[0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],	// 'T'
+	[0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],	// 'U'
+	[0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],	// 'V'
+	[0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],	// 'W'
+	[0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],	// 'X'
+	[0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],	// 'Y'
+	[0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],	// 'Z'
+	[0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E],	// '['
+	[0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00],	// '\\'
+	[0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E],	// ']'
+	[0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00],	// '^'
+	[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],	// '_'
+	[0x08, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00],	// '`'
+	[0x00, 0x00, 0x0E, 0x01, 0x0F, 0x11, 0x0F],	// 'a'
+	[0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1E],	// 'b'
+	[0x00, 0x00, 0x0E, 0x10, 0x10, 0x11, 0x0E],	// 'c'
+	[0x01, 0x01, 0x0D, 0x13, 0x11, 0x11, 0x0F],	// 'd'
+	[0x00, 0x00, 0x0E, 0x11, 0x1F, 0x10, 0x0E],	// 'e'
+	[0x06, 0x09, 0x08, 0x1C, 0x08, 0x08, 0x08],	// 'f'
+	[0x00, 0x0F, 0x11, 0x11, 0x0F, 0x01, 0x0E],	// 'g'
+	[0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11],	// 'h'
+	[0x04, 0x00, 0x0C, 0x04, 0x04, 0x04, 0x0E],	// 'i'
+	[0x02, 0x00, 0x06, 0x02, 0x02, 0x12, 0x0C],	// 'j'
+	[0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12],	// 'k'
+	[0x0C, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],	// 'l'
+	[0x00, 0x00, 0x1A, 0x15, 0x15, 0x11, 0x11],	// 'm'
+	[0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11],	// 'n'
+	[0x00, 0x00, 0x0E, 0x11, 0x11, 0x11, 0x0E],	// 'o'
+	[0x00, 0x00, 0x1E, 0x11, 0x1E, 0x10, 0x10],	// 'p'
+	[0x00, 0x00, 0x0D, 0x13, 0x0F, 0x01, 0x01],	// 'q'
+	[0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10],	// 'r'
+	[0x00, 0x00, 0x0E, 0x10, 0x0E, 0x01, 0x1E],	// 's'
+	[0x08, 0x08, 0x1C, 0x08, 0x08, 0x09, 0x06],	// 't'
+	[0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0D],	// 'u'
+	[0x00, 0x00, 0x11, 0x11, 0x11, 0x0A, 0x04],	// 'v'
+	[0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0A],	// 'w'
+	[0x00, 0x00, 0x11, 0x0A, 0x04, 0x0A, 0x11],	// 'x'
+	[0x00, 0x00, 0x11, 0x11, 0x0F, 0x01, 0x0E],	// 'y'
+	[0x00, 0x00, 0x1F, 0x02, 0x04, 0x08, 0x1F],	// 'z'
+	[0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02],	// '{'
+	[0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],	// '|'
+	[0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08],	// '}'
+	[0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00]	// '~'
+];
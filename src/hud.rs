@@ -0,0 +1,138 @@
+use image::{RgbaImage, Rgba};
+use std::borrow::Cow;
+
+use machine::Machine;
+use disassemble::{disassemble, instr_len};
+use font::{draw_text, clear, CHAR_W, CHAR_H, TEXT, LABEL};
+
+const WIDTH_CHARS: u32 = 30;
+const HEIGHT_CHARS: u32 = 30;
+// instructions shown before and after the one at PC
+const BEFORE: usize = 4;
+const AFTER: usize = 6;
+// words shown from the top of the stack
+const STACK_WORDS: u16 = 6;
+
+const DIM: Rgba<u8> = Rgba { data: [0x50, 0x50, 0x50, 0xFF] };
+const CURRENT: Rgba<u8> = Rgba { data: [0xFF, 0xE0, 0x40, 0xFF] };
+
+// Registers, flags, the code around PC, the stack and the interrupt timing, drawn over the game.
+#[derive(Debug)]
+pub struct DebugHud {
+	open: bool,
+	image: RgbaImage
+}
+
+impl DebugHud {
+	pub fn new() -> DebugHud {
+		DebugHud {
+			open: false,
+			image: RgbaImage::new(WIDTH_CHARS * CHAR_W, HEIGHT_CHARS * CHAR_H)
+		}
+	}
+
+	pub fn is_open(&self) -> bool {
+		self.open
+	}
+
+	pub fn toggle(&mut self) {
+		self.open = !self.open;
+	}
+
+	// width in pixels of the image render draws
+	pub fn width(&self) -> u32 {
+		self.image.width()
+	}
+
+	pub fn render(&mut self, m: &Machine) -> &RgbaImage {
+		let img = &mut self.image;
+		clear(img);
+		let cpu = m.cpu();
+		let mem = m.memory();
+
+		let regs = [("A", format!("{:02X}", cpu.a)), ("BC", format!("{:04X}", cpu.get_bc())),
+			("DE", format!("{:04X}", cpu.get_de())), ("HL", format!("{:04X}", cpu.get_hl())),
+			("SP", format!("{:04X}", cpu.sp)), ("PC", format!("{:04X}", cpu.pc))];
+		for (i, &(name, ref value)) in regs.iter().enumerate() {
+			let (col, row) = (i as u32 % 3 * 9, i as u32 / 3);
+			draw_text(img, col, row, name, LABEL, None);
+			draw_text(img, col + 3, row, value, TEXT, None);
+		}
+
+		// set flags bright, cleared ones dim
+		let cc = &cpu.cc;
+		let flags = [("S", cc.s()), ("Z", cc.z()), ("AC", cc.ac()), ("P", cc.p()), ("CY", cc.cy())];
+		draw_text(img, 0, 2, "FLAGS", LABEL, None);
+		let mut col = 6;
+		for &(name, value) in flags.iter() {
+			draw_text(img, col, 2, name, if value == 1 { CURRENT } else { DIM }, None);
+			col += name.len() as u32 + 1;
+		}
+
+		draw_text(img, 0, 3, "INTE", LABEL, None);
+		draw_text(img, 5, 3, &cpu.int_enable.to_string(), TEXT, None);
+		if m.is_halted() {
+			draw_text(img, 9, 3, "HALTED", CURRENT, None);
+		}
+		let (rst, cycles) = m.next_interrupt();
+		let phase = if rst == 1 { "mid-screen" } else { "end of frame" };
+		draw_text(img, 0, 4, "NEXT", LABEL, None);
+		draw_text(img, 5, 4, &format!("RST {} {} in {}", rst, phase, cycles), TEXT, None);
+		draw_text(img, 0, 5, "CYCLES", LABEL, None);
+		draw_text(img, 7, 5, &m.cycles().to_string(), TEXT, None);
+		draw_text(img, 0, 6, "FRAME", LABEL, None);
+		draw_text(img, 7, 6, &m.frames().to_string(), TEXT, None);
+		draw_text(img, 0, 7, "INSTR", LABEL, None);
+		draw_text(img, 7, 7, &m.instructions().to_string(), TEXT, None);
+
+		let mut row = 9;
+		for (addr, text) in code_around(mem, cpu.pc) {
+			let current = addr == cpu.pc;
+			let line = format!("{}{:04X} {}", if current { ">" } else { " " }, addr, text);
+			draw_text(img, 0, row, &line, if current { CURRENT } else { TEXT }, None);
+			row += 1;
+		}
+
+		row = 9 + (BEFORE + AFTER + 1) as u32 + 1;
+		draw_text(img, 0, row, "STACK", LABEL, None);
+		for i in 0..STACK_WORDS {
+			let addr = cpu.sp.wrapping_add(i * 2);
+			let word = (mem[addr.wrapping_add(1) as usize] as u16) << 8 | mem[addr as usize] as u16;
+			draw_text(img, 1, row + 1 + i as u32, &format!("{:04X} {:04X}", addr, word), TEXT, None);
+		}
+		&self.image
+	}
+}
+
+// Instructions around PC. Code can't be decoded backwards, so it is decoded from a bit earlier
+// on and the start that lines up with PC after the most instructions is taken.
+fn code_around(mem: &[u8], pc: u16) -> Vec<(u16, Cow<'static, str>)> {
+	let decode = |addr: u16| {
+		let a = addr as usize;
+		disassemble(mem[a], mem[(a + 1) & 0xFFFF], mem[(a + 2) & 0xFFFF])
+	};
+
+	let mut before: Vec<u16> = Vec::new();
+	for back in (1..BEFORE as u16 * 3 + 1).rev() {
+		let mut addr = pc.wrapping_sub(back);
+		let mut starts = Vec::new();
+		// until PC is reached or stepped over
+		while addr != pc && pc.wrapping_sub(addr) <= back {
+			starts.push(addr);
+			addr = addr.wrapping_add(instr_len(mem[addr as usize]) as u16);
+		}
+		if addr == pc && starts.len() > before.len() {
+			before = starts;
+		}
+	}
+
+	let skip = before.len().saturating_sub(BEFORE);
+	let mut lines: Vec<(u16, Cow<'static, str>)> = before[skip..].iter().map(|&a| (a, decode(a).0)).collect();
+	let mut addr = pc;
+	for _ in 0..AFTER + 1 {
+		let (text, len) = decode(addr);
+		lines.push((addr, text));
+		addr = addr.wrapping_add(len as u16);
+	}
+	lines
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use piston_window::Key;
+use toml;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Input {
+	Coin,
+	P1Start,
+	P2Start,
+	P1Fire,
+	P1Left,
+	P1Right,
+	P2Fire,
+	P2Left,
+	P2Right,
+	Tilt
+}
+
+impl Input {
+	pub const ALL: [Input; 10] = [
+		Input::Coin, Input::P1Start, Input::P2Start,
+		Input::P1Fire, Input::P1Left, Input::P1Right,
+		Input::P2Fire, Input::P2Left, Input::P2Right,
+		Input::Tilt
+	];
+
+	// input port and bit the cabinet wires this input to
+	pub fn port_bit(&self) -> (u8, u8) {
+		match *self {
+			Input::Coin => (1, 0),
+			Input::P2Start => (1, 1),
+			Input::P1Start => (1, 2),
+			Input::P1Fire => (1, 4),
+			Input::P1Left => (1, 5),
+			Input::P1Right => (1, 6),
+			Input::Tilt => (2, 2),
+			Input::P2Fire => (2, 4),
+			Input::P2Left => (2, 5),
+			Input::P2Right => (2, 6)
+		}
+	}
+
+	// the matching player 2 control, upright cabinets share one control panel
+	pub fn player2(&self) -> Option<Input> {
+		match *self {
+			Input::P1Fire => Some(Input::P2Fire),
+			Input::P1Left => Some(Input::P2Left),
+			Input::P1Right => Some(Input::P2Right),
+			_ => None
+		}
+	}
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Bindings {
+	keys: HashMap<Key, Input>
+}
+
+impl Bindings {
+	pub fn new() -> Bindings {
+		let mut keys = HashMap::new();
+		keys.insert(Key::C, Input::Coin);
+		keys.insert(Key::Return, Input::P1Start);
+		keys.insert(Key::D1, Input::P1Start);
+		keys.insert(Key::D2, Input::P2Start);
+		keys.insert(Key::Space, Input::P1Fire);
+		keys.insert(Key::Period, Input::P1Fire);
+		keys.insert(Key::Left, Input::P1Left);
+		keys.insert(Key::Z, Input::P1Left);
+		keys.insert(Key::Right, Input::P1Right);
+		keys.insert(Key::X, Input::P1Right);
+		keys.insert(Key::W, Input::P2Fire);
+		keys.insert(Key::A, Input::P2Left);
+		keys.insert(Key::D, Input::P2Right);
+
+		Bindings {
+			keys
+		}
+	}
+
+	pub fn empty() -> Bindings {
+		Bindings {
+			keys: HashMap::new()
+		}
+	}
+
+	pub fn get(&self, key: Key) -> Option<Input> {
+		self.keys.get(&key).cloned()
+	}
+
+	pub fn bind(&mut self, key: Key, input: Input) {
+		self.keys.insert(key, input);
+	}
+
+	pub fn unbind_input(&mut self, input: Input) {
+		self.keys.retain(|_, i| *i != input);
+	}
+
+	pub fn keys_for(&self, input: Input) -> Vec<Key> {
+		let mut keys: Vec<Key> = self.keys.iter()
+			.filter(|&(_, i)| *i == input)
+			.map(|(k, _)| *k)
+			.collect();
+		keys.sort_by_key(|k| k.code());
+		keys
+	}
+
+	// config file uses the piston key names, e.g. `Space = "P1Fire"`
+	pub fn from_table(table: &toml::value::Table) -> Bindings {
+		let mut bindings = Bindings::empty();
+		for (name, value) in table {
+			let key = toml::Value::String(name.clone()).try_into::<Key>();
+			let input = value.clone().try_into::<Input>();
+			match (key, input) {
+				(Ok(key), Ok(input)) => bindings.bind(key, input),
+				_ => println!("=> Ignoring invalid key binding: {} = {}", name, value)
+			}
+		}
+		bindings
+	}
+
+	pub fn to_table(&self) -> toml::value::Table {
+		let mut table = toml::value::Table::new();
+		for (key, input) in &self.keys {
+			table.insert(format!("{:?}", key), toml::Value::String(format!("{:?}", input)));
+		}
+		table
+	}
+}
+
+
+#[derive(Debug, Clone)]
+pub struct PadBindings {
+	buttons: HashMap<u8, Input>,
+	// (axis, positive direction) => input
+	axes: HashMap<(u8, bool), Input>,
+	pub deadzone: f64
+}
+
+impl PadBindings {
+	pub fn new() -> PadBindings {
+		let mut buttons = HashMap::new();
+		buttons.insert(0, Input::P1Fire);
+		buttons.insert(6, Input::Coin);
+		buttons.insert(7, Input::P1Start);
+
+		let mut axes = HashMap::new();
+		axes.insert((0, false), Input::P1Left);
+		axes.insert((0, true), Input::P1Right);
+
+		PadBindings {
+			buttons, axes, deadzone: 0.3
+		}
+	}
+
+	pub fn button(&self, button: u8) -> Option<Input> {
+		self.buttons.get(&button).cloned()
+	}
+
+	pub fn axis(&self, axis: u8, positive: bool) -> Option<Input> {
+		self.axes.get(&(axis, positive)).cloned()
+	}
+
+	// buttons are named by number, axes by number and direction, e.g. `"0-" = "P1Left"`
+	pub fn from_table(table: &toml::value::Table) -> PadBindings {
+		let mut bindings = PadBindings::new();
+
+		if let Some(deadzone) = table.get("deadzone").and_then(|v| v.as_float()) {
+			bindings.deadzone = deadzone.max(0.0).min(1.0);
+		}
+
+		if let Some(buttons) = table.get("buttons").and_then(|v| v.as_table()) {
+			bindings.buttons.clear();
+			for (name, value) in buttons {
+				match (name.parse::<u8>(), value.clone().try_into::<Input>()) {
+					(Ok(button), Ok(input)) => {bindings.buttons.insert(button, input);},
+					_ => println!("=> Ignoring invalid gamepad button binding: {} = {}", name, value)
+				}
+			}
+		}
+
+		if let Some(axes) = table.get("axes").and_then(|v| v.as_table()) {
+			bindings.axes.clear();
+			for (name, value) in axes {
+				match (parse_axis(name), value.clone().try_into::<Input>()) {
+					(Some(axis), Ok(input)) => {bindings.axes.insert(axis, input);},
+					_ => println!("=> Ignoring invalid gamepad axis binding: {} = {}", name, value)
+				}
+			}
+		}
+
+		bindings
+	}
+
+	pub fn to_table(&self) -> toml::value::Table {
+		let mut buttons = toml::value::Table::new();
+		for (button, input) in &self.buttons {
+			buttons.insert(button.to_string(), toml::Value::String(format!("{:?}", input)));
+		}
+
+		let mut axes = toml::value::Table::new();
+		for (&(axis, positive), input) in &self.axes {
+			let name = format!("{}{}", axis, if positive {'+'} else {'-'});
+			axes.insert(name, toml::Value::String(format!("{:?}", input)));
+		}
+
+		let mut table = toml::value::Table::new();
+		table.insert("deadzone".to_string(), toml::Value::Float(self.deadzone));
+		table.insert("buttons".to_string(), toml::Value::Table(buttons));
+		table.insert("axes".to_string(), toml::Value::Table(axes));
+		table
+	}
+}
+
+fn parse_axis(name: &str) -> Option<(u8, bool)> {
+	let positive = match name.chars().last() {
+		Some('+') => true,
+		Some('-') => false,
+		_ => return None
+	};
+	name[..name.len() - 1].parse::<u8>().ok().map(|axis| (axis, positive))
+}
+
+
+// Tracks the axis state of every controller seen so far, so pads plugged in
+// while running are picked up and only direction changes reach the machine.
+#[derive(Debug)]
+pub struct Gamepads {
+	connected: Vec<i32>,
+	// (pad, axis) => -1, 0 or 1
+	directions: HashMap<(i32, u8), i8>
+}
+
+impl Gamepads {
+	pub fn new() -> Gamepads {
+		Gamepads {
+			connected: Vec::new(),
+			directions: HashMap::new()
+		}
+	}
+
+	pub fn seen(&mut self, pad: i32) {
+		if !self.connected.contains(&pad) {
+			self.connected.push(pad);
+			println!("=> Controller {} connected", pad);
+		}
+	}
+
+	// returns the inputs to release and press, in that order
+	pub fn axis_moved(&mut self, pad: i32, axis: u8, position: f64, bindings: &PadBindings)
+		-> Vec<(Input, bool)> {
+		self.seen(pad);
+
+		let dir = if position > bindings.deadzone {
+			1
+		}
+		else if position < -bindings.deadzone {
+			-1
+		}
+		else {
+			0
+		};
+
+		let old = self.directions.insert((pad, axis), dir).unwrap_or(0);
+		let mut changes = Vec::new();
+		if old != dir {
+			if old != 0 {
+				if let Some(input) = bindings.axis(axis, old > 0) {
+					changes.push((input, false));
+				}
+			}
+			if dir != 0 {
+				if let Some(input) = bindings.axis(axis, dir > 0) {
+					changes.push((input, true));
+				}
+			}
+		}
+		changes
+	}
+}
+
+
+// Walks through all inputs and binds the next key pressed to each of them.
+#[derive(Debug)]
+pub struct RebindMenu {
+	pos: Option<usize>,
+	// bindings from before, for cancelling
+	previous: Option<Bindings>
+}
+
+impl RebindMenu {
+	pub fn new() -> RebindMenu {
+		RebindMenu {
+			pos: None,
+			previous: None
+		}
+	}
+
+	pub fn is_open(&self) -> bool {
+		self.pos.is_some()
+	}
+
+	pub fn open(&mut self, bindings: &Bindings) {
+		self.pos = Some(0);
+		self.previous = Some(bindings.clone());
+	}
+
+	pub fn close(&mut self) {
+		self.pos = None;
+		self.previous = None;
+	}
+
+	// restores the bindings from before the menu was opened
+	pub fn cancel(&mut self, bindings: &mut Bindings) {
+		if let Some(previous) = self.previous.take() {
+			*bindings = previous;
+		}
+		self.pos = None;
+	}
+
+	pub fn prompt(&self) -> Option<String> {
+		self.pos.map(|pos| {
+			format!("Press key for {:?} ({}/{}, Backspace: skip, Esc: cancel)", Input::ALL[pos], pos + 1, Input::ALL.len())
+		})
+	}
+
+	// returns true once the last input has been handled
+	pub fn handle_key(&mut self, key: Key, bindings: &mut Bindings) -> bool {
+		let pos = match self.pos {
+			Some(pos) => pos,
+			None => return false
+		};
+
+		if key != Key::Backspace {
+			let input = Input::ALL[pos];
+			bindings.unbind_input(input);
+			bindings.bind(key, input);
+		}
+
+		if pos + 1 < Input::ALL.len() {
+			self.pos = Some(pos + 1);
+			false
+		}
+		else {
+			self.close();
+			true
+		}
+	}
+}
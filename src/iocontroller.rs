@@ -1,19 +1,22 @@
-#[cfg(feature = "audio")]
-extern crate ears;
-#[cfg(feature = "audio")]
-use std::vec::Vec;
-#[cfg(feature = "audio")]
-use self::ears::{AudioController, Sound};
-
-use std::fmt;
-use piston_window::Key;
+use input::Input;
+use sound::*;
+use audio::*;
 
 
 #[derive(Debug)]
 pub struct IOController {
-	input: u8,
+	input1: u8,
+	input2: u8,
+
+	audio: Box<dyn AudioSink>,
+	ports: SoundPorts,
+	timeline: Option<SoundTimeline>,
 
-	audio: Audio,
+	// port and bit of the flip output, whether it is set
+	flip: Option<(u8, u8)>,
+	flipped: bool,
+	// separate controls per player instead of one shared panel
+	cocktail: bool,
 
 	shift0: u8,
 	shift1: u8,
@@ -23,34 +26,70 @@ pub struct IOController {
 impl IOController {
 	pub fn new() -> IOController {
 		IOController {
-			input: 4, audio: Audio::new(), shift0: 0, shift1: 0, shift_offset: 0
+			input1: 4, input2: 0, audio: Box::new(NullSink), ports: SoundPorts::new(), timeline: None,
+			flip: None, flipped: false, cocktail: false,
+			shift0: 0, shift1: 0, shift_offset: 0
 		}
 	}
 
-	pub fn write(&mut self, port: u8, value: u8) {
+	pub fn set_audio(&mut self, audio: Box<dyn AudioSink>) {
+		self.audio = audio;
+	}
+
+	pub fn set_flip_output(&mut self, flip: Option<(u8, u8)>) {
+		self.flip = flip;
+		self.flipped = false;
+	}
+
+	pub fn set_cocktail(&mut self, cocktail: bool) {
+		self.cocktail = cocktail;
+	}
+
+	pub fn flipped(&self) -> bool {
+		self.flipped
+	}
+
+	pub fn write(&mut self, port: u8, value: u8, cycle: u64) {
+		if let Some((flip_port, bit)) = self.flip {
+			if port == flip_port {
+				self.flipped = (value >> bit) & 1 == 1;
+			}
+		}
+
 		match port {
 			2 => {
 				self.shift_offset = value & 0x7;
 			},
-			3 => {
-				self.audio.play(3, value);
+			3 | 5 => {
+				self.play(port, value, cycle);
 			},
 			4 => {
 				self.shift0 = self.shift1;
 				self.shift1 = value;
 			},
-			5 => {
-				self.audio.play(5, value);
-			},
 			_ => ()
 		};
 	}
 
+	fn play(&mut self, port: u8, value: u8, cycle: u64) {
+		for (sound, on) in self.ports.write(port, value) {
+			if on {
+				self.audio.start(sound, cycle);
+			}
+			else {
+				self.audio.stop(sound, cycle);
+			}
+			if let Some(ref mut timeline) = self.timeline {
+				timeline.push(SoundEvent {cycle, sound, on});
+			}
+		}
+	}
+
 	pub fn read(&self, port: u8) -> u8 {
 		match port {
 			0 => 0xF,
-			1 => self.input,
-			2 => 0,
+			1 => self.input1,
+			2 => self.input2,
 			3 => {
 				let v = ((self.shift1 as u16) << 8) | self.shift0 as u16;
 				(v >> (8 - self.shift_offset)) as u8
@@ -59,94 +98,34 @@ impl IOController {
 		}
 	}
 
-	pub fn set_key(&mut self, key: Key, value: u8) {
-		let shift = match key {
-			Key::C => 0,
-			Key::Return => 2,
-			Key::D1 => 2,
-			Key::Space => 4,
-			Key::Period => 4,
-			Key::Left => 5,
-			Key::Z => 5,
-			Key::Right => 6,
-			Key::X => 6,
-			_ => 7
-		};
-		if value == 1 {
-			//self.input ^= 1 << shift;
-			self.input |= 1 << shift;
-		}
-		else {
-			self.input &= !(1 << shift);
-		}
+	pub fn record_sound(&mut self, enable: bool) {
+		self.timeline = if enable { Some(SoundTimeline::new()) } else { None };
 	}
-}
-
-#[cfg(feature = "audio")]
-struct Audio {
-	sounds: Vec<Sound>
-}
 
-#[cfg(feature = "audio")]
-impl Audio {
-	fn new() -> Self {
-		let mut sounds: Vec<Sound> = Vec::new();
-		for i in 0..9 {
-			let file = &format!("sound/{}.wav", i);
-			let error = &format!("sound file not found: {}", file);
-			sounds.push(Sound::new(file).expect(error));
+	pub fn sound_events(&mut self) -> Vec<SoundEvent> {
+		match self.timeline {
+			Some(ref mut timeline) => timeline.drain(),
+			None => Vec::new()
 		}
-		sounds[0].set_looping(true); // loop UFO sound
+	}
 
-		Audio {
-			sounds
+	pub fn set_input(&mut self, input: Input, pressed: bool) {
+		if !self.cocktail {
+			if let Some(p2) = input.player2() {
+				self.set_bit(p2, pressed);
+			}
 		}
+		self.set_bit(input, pressed);
 	}
 
-	fn play(&mut self, port: u8, value: u8) {
-		if port == 3 {
-			for i in 0..4 {
-				if (value >> i) & 1 == 1 {
-					if !self.sounds[i].is_playing() {
-						self.sounds[i].play();
-					}
-				}
-			}
-			if value & 1 == 0 {
-				if self.sounds[0].is_playing() {
-					self.sounds[0].stop(); // stop UFO sound if needed
-				}
-			}
+	fn set_bit(&mut self, input: Input, pressed: bool) {
+		let (port, shift) = input.port_bit();
+		let reg = if port == 1 { &mut self.input1 } else { &mut self.input2 };
+		if pressed {
+			*reg |= 1 << shift;
 		}
 		else {
-			for i in 4..9 {
-				if (value >> (i - 4)) & 1 == 1 {
-					if !self.sounds[i].is_playing() {
-						self.sounds[i].play();
-					}
-				}
-			}
+			*reg &= !(1 << shift);
 		}
 	}
 }
-
-
-// Dummy Version if audio not enabled
-
-#[cfg(not(feature = "audio"))]
-struct Audio {}
-
-#[cfg(not(feature = "audio"))]
-impl Audio {
-	fn new() -> Self {
-		Audio {}
-	}
-
-	fn play(&mut self, _port: u8, _value: u8) {}
-}
-
-impl fmt::Debug for Audio {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "Audio")
-	}
-}
@@ -0,0 +1,50 @@
+#![allow(unused_mut)]
+#![allow(dead_code)]
+
+// The emulator core, shared by the binary and the benchmarks.
+
+extern crate image;
+extern crate piston_window;
+extern crate toml;
+extern crate serde;
+extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate lazy_static;
+#[cfg(test)]
+#[macro_use]
+extern crate quickcheck;
+
+pub mod machine;
+pub mod cpu;
+pub mod alu;
+pub mod operation;
+pub mod bus;
+pub mod disassemble;
+pub mod iocontroller;
+pub mod input;
+pub mod config;
+pub mod synth;
+pub mod sound;
+pub mod audio;
+pub mod display;
+pub mod profile;
+pub mod artwork;
+pub mod view;
+pub mod filter;
+pub mod capture;
+pub mod clock;
+pub mod trace;
+pub mod tracediff;
+pub mod vectors;
+pub mod error;
+pub mod font;
+pub mod memview;
+pub mod hud;
+
+pub const DIAG: bool = false;
+pub const DFIX: bool = false;
+
+pub const WIDTH: u32 = 224;
+pub const HEIGHT: u32 = 256;
@@ -3,20 +3,43 @@ use alu::ALU;
 use operation::*;
 use disassemble::*;
 use iocontroller::*;
+use input::Input;
+use sound::SoundEvent;
+use audio::AudioSink;
+use trace::Tracer;
+use error::EmuError;
+use bus::{Access, AccessKind, LoggingBus, log_fetch};
 
 use std::io::*;
 use std::vec::Vec;
 use std::fs::File;
-use std::time::SystemTime;
-use piston_window::Key;
 
 use DFIX;
 
-const NANOS_PER_SEC: u64 = 1_000_000_000;
-const CPU_HZ: u64 = 2_000_000;
-const SCREEN_HZ: u64 = 60;
-const NANOS_PER_CYCLE: u64 = NANOS_PER_SEC / CPU_HZ;
-const INTERRUPT_CYCLES: i64 = (CPU_HZ / SCREEN_HZ / 2) as i64;
+const MEM_SIZE: usize = 0x10000;
+// warnings kept until they are taken, more are dropped
+const MAX_WARNINGS: usize = 100;
+
+pub const CPU_HZ: u64 = 2_000_000;
+pub const SCREEN_HZ: u64 = 60;
+pub const CYCLES_PER_FRAME: u64 = CPU_HZ / SCREEN_HZ;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+	// emulated seconds per real second, 1 is real speed
+	Factor(f64),
+	// as fast as the host can
+	Unthrottled
+}
+
+impl Speed {
+	pub fn name(&self) -> String {
+		match *self {
+			Speed::Factor(f) => format!("{}x", f),
+			Speed::Unthrottled => "unthrottled".to_string()
+		}
+	}
+}
 
 fn read_file(filename: &str) -> Result<Vec<u8>> {
 	let mut file = File::open(&filename)?;
@@ -35,14 +58,28 @@ pub struct Machine {
 	int_type: u8,
 	halt: bool,
 	steps: u64,
-	time: SystemTime,
-	cycles_to_int: i64
+	cycles: u64,
+	frames: u64,
+	// cycle the next interrupt is due at
+	next_int: u64,
+
+	// honoured by the frame clock, run_frame always runs
+	paused: bool,
+	speed: Speed,
+
+	tracer: Option<Tracer>,
+	breakpoints: Vec<u16>,
+	// breakpoint just hit, not triggered again when continuing from it
+	resume_pc: Option<u16>,
+	warnings: Vec<EmuError>,
+	// bus accesses of the last step, when logging
+	accesses: Option<Vec<Access>>
 }
 
 impl Machine {
 	pub fn new() -> Machine {
 		let mut mem: Vec<u8> = Vec::new();
-		mem.resize(0x10000, 0);
+		mem.resize(MEM_SIZE, 0);
 		if DFIX {
 			// prevent "reboot"
 			mem[0] = 0x76;
@@ -57,51 +94,125 @@ impl Machine {
 			int_type: 1,
 			halt: false,
 			steps: 0,
-			time: SystemTime::now(),
-			cycles_to_int: INTERRUPT_CYCLES
+			cycles: 0,
+			frames: 0,
+			next_int: CYCLES_PER_FRAME / 2,
+
+			paused: false,
+			speed: Speed::Factor(1.0),
+
+			tracer: None,
+			breakpoints: Vec::new(),
+			resume_pc: None,
+			warnings: Vec::new(),
+			accesses: None
 		}
 	}
 
-	fn emulate_op(&mut self, prnt: bool) -> u8 {
+	fn emulate_op(&mut self) -> u8 {
 		let mut cpu = &mut self.cpu;
 		let mut alu = &mut self.alu;
 		let mut mem = &mut self.mem;
 		let io = &mut self.io;
+		let accesses = &mut self.accesses;
+		let cycle = self.cycles;
 		let pc_addr = cpu.pc as usize;
+		let sp = cpu.sp;
+		// addresses wrap around like on the 16 bit bus
 		let opcode = mem[pc_addr];
-		let arg1 = mem[pc_addr + 1];
-		let arg2 = mem[pc_addr + 2];
-		
-		if prnt {
-			print!("{} \t", self.steps);
-			print_instr(opcode, arg1, arg2, cpu.pc);
+		let arg1 = mem[(pc_addr + 1) % MEM_SIZE];
+		let arg2 = mem[(pc_addr + 2) % MEM_SIZE];
+
+		if let Some(ref mut log) = *accesses {
+			log_fetch(log, cpu.pc, [opcode, arg1, arg2], instr_len(opcode));
 		}
 
 		let cycles = match opcode {
-			0xD3 => {io.write(arg1, cpu.a); cpu.pc = cpu.pc.wrapping_add(2); 3}, // OUT
-			0xDB => {cpu.a = io.read(arg1); cpu.pc = cpu.pc.wrapping_add(2); 3}, // IN
-			_ => execute(&mut cpu, &mut alu, &mut mem, opcode, arg1, arg2)
+			0xD3 => { // OUT
+				io.write(arg1, cpu.a, cycle);
+				if let Some(ref mut log) = *accesses {
+					log.push(Access {addr: arg1 as u16, value: cpu.a, kind: AccessKind::IoWrite});
+				}
+				cpu.pc = cpu.pc.wrapping_add(2);
+				3
+			},
+			0xDB => { // IN
+				cpu.a = io.read(arg1);
+				if let Some(ref mut log) = *accesses {
+					log.push(Access {addr: arg1 as u16, value: cpu.a, kind: AccessKind::IoRead});
+				}
+				cpu.pc = cpu.pc.wrapping_add(2);
+				3
+			},
+			_ => match *accesses {
+				Some(ref mut log) => execute(&mut cpu, &mut alu, &mut LoggingBus::new(mem, log), opcode, arg1, arg2),
+				None => execute(&mut cpu, &mut alu, mem, opcode, arg1, arg2)
+			}
 		};
 
-		if prnt {
-			print!(" \t|\t");
-			cpu.print_state();
+		if let Some(ref mut tracer) = self.tracer {
+			tracer.instruction(cycle, pc_addr as u16, opcode, arg1, arg2, cpu);
+		}
+		// LXI SP and SPHL may set SP anywhere, only pushes and pops can run over the ends
+		if opcode != 0x31 && opcode != 0xF9 {
+			let new_sp = self.cpu.sp;
+			self.check_stack(pc_addr as u16, sp, new_sp);
 		}
 
 		cycles
 	}
 
+	// a push below 0 or a pop above FFFF, the program most likely went astray
+	fn check_stack(&mut self, pc: u16, old: u16, new: u16) {
+		let wrapped = (new == old.wrapping_sub(2) && new > old) || (new == old.wrapping_add(2) && new < old);
+		if wrapped {
+			self.warn(EmuError::StackWrap {pc, sp: new});
+		}
+	}
+
+	fn warn(&mut self, warning: EmuError) {
+		if self.warnings.len() < MAX_WARNINGS {
+			self.warnings.push(warning);
+		}
+	}
+
+	// problems noticed since the last call
+	pub fn take_warnings(&mut self) -> Vec<EmuError> {
+		self.warnings.drain(..).collect()
+	}
+
 	fn interrupt(&mut self) {
 		if self.cpu.int_enable == 1 {
-			generate_interrupt(&mut self.cpu, &mut self.mem, self.int_type);
-			self.int_type = (!self.int_type) & 3;
+			let (pc, sp) = (self.cpu.pc, self.cpu.sp);
+			match self.accesses {
+				Some(ref mut log) => generate_interrupt(&mut self.cpu, &mut LoggingBus::new(&mut self.mem, log), self.int_type),
+				None => generate_interrupt(&mut self.cpu, &mut self.mem, self.int_type)
+			}
+			let new_sp = self.cpu.sp;
+			self.check_stack(pc, sp, new_sp);
+			if let Some(ref mut tracer) = self.tracer {
+				tracer.interrupt(self.cycles, pc, self.int_type, &self.cpu);
+			}
+		}
+	}
+
+	// The video hardware interrupts in the middle of the screen (RST 1) and at the end of it (RST 2),
+	// always at the same position in the frame: the first due after cycle and its RST number.
+	fn interrupt_after(cycle: u64) -> (u64, u8) {
+		let frame = cycle / CYCLES_PER_FRAME * CYCLES_PER_FRAME;
+		let middle = frame + CYCLES_PER_FRAME / 2;
+		if cycle < middle {
+			(middle, 1)
+		}
+		else {
+			(frame + CYCLES_PER_FRAME, 2)
 		}
 	}
 
 	pub fn dump_mem(&self, offset: usize, len: usize) {
 		for i in 0..len {
-			let n = offset + i;
-			print_instr(self.mem[n], self.mem[n + 1], self.mem[n + 2], n as u16);
+			let n = (offset + i) % MEM_SIZE;
+			print_instr(self.mem[n], self.mem[(n + 1) % MEM_SIZE], self.mem[(n + 2) % MEM_SIZE], n as u16);
 			println!("");
 		}
 	}
@@ -110,50 +221,331 @@ impl Machine {
 		&self.mem[0x2400..0x4000]
 	}
 
-	pub fn load_rom(&mut self, filename: &str, offset: usize) {
-		let buffer = read_file(&filename).expect(&format!("Can't read ROM file: {}", filename));
+	pub fn load_rom(&mut self, filename: &str, offset: usize) -> ::std::result::Result<(), EmuError> {
+		let buffer = read_file(&filename).map_err(|e| EmuError::RomUnreadable(filename.to_string(), e))?;
+		self.load_program(&buffer, offset)?;
+		println!("=> {} bytes loaded to {:#03X}", buffer.len(), offset);
+		Ok(())
+	}
 
-		let l = buffer.len() + offset;
-		self.mem[offset..l].copy_from_slice(&buffer);
+	// Copies code to memory and starts execution there.
+	pub fn load_program(&mut self, program: &[u8], offset: usize) -> ::std::result::Result<(), EmuError> {
+		let l = program.len() + offset;
+		if l > MEM_SIZE {
+			return Err(EmuError::RomTooLarge {size: program.len(), offset});
+		}
+		self.mem[offset..l].copy_from_slice(program);
 		self.cpu.pc = offset as u16;
-		println!("=> {} bytes loaded to {:#03X}", buffer.len(), offset);
+		Ok(())
+	}
+
+	// Runs one video frame. Frame n ends at cycle n * CYCLES_PER_FRAME, so the few cycles
+	// the last instruction runs over are taken from the next frame instead of adding up.
+	pub fn run_frame(&mut self) {
+		self.frames += 1;
+		let end = self.frames * CYCLES_PER_FRAME;
+		let cycles = end.saturating_sub(self.cycles);
+		self.run_cycles(cycles);
 	}
 
-	pub fn run(&mut self) {
-		match self.time.elapsed() {
-			Ok(elapsed) => {
-				let nanos_elapsed = (elapsed.as_secs() * NANOS_PER_SEC) + (elapsed.subsec_nanos() as u64);
-				let mut cycles_needed = (nanos_elapsed / NANOS_PER_CYCLE) as i64;
+	// frames run since the start
+	pub fn frames(&self) -> u64 {
+		self.frames
+	}
 
-				while !self.halt && cycles_needed > 0 {
-					let cycles = self.emulate_op(false) as i64;
-					self.steps += 1;
+	pub fn pause(&mut self) {
+		self.paused = true;
+	}
 
-					if cycles == 0 {
-						self.halt = true;
-						println!("\n=> CPU halted\n");
-					}
-					cycles_needed -= cycles;
-					self.cycles_to_int -= cycles;
+	pub fn resume(&mut self) {
+		self.paused = false;
+	}
 
-					if self.cycles_to_int <= 0 {
-						self.interrupt();
-						self.cycles_to_int += INTERRUPT_CYCLES;
-					}
-				}
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	// pauses and runs exactly one frame
+	pub fn advance_frame(&mut self) {
+		self.paused = true;
+		self.run_frame();
+	}
+
+	pub fn set_speed(&mut self, speed: Speed) {
+		self.speed = speed;
+	}
+
+	pub fn speed(&self) -> Speed {
+		self.speed
+	}
+
+	pub fn run_cycles(&mut self, cycles: u64) {
+		let mut cycles_needed = cycles as i64;
+
+		while !self.halt && cycles_needed > 0 {
+			if self.hit_breakpoint() {
+				break;
 			}
-			Err(e) => {
-				println!("Time error: {:?}", e);
+			cycles_needed -= self.step() as i64;
+		}
+	}
+
+	// Runs a single instruction and the interrupt due after it, returns the cycles taken.
+	pub fn step(&mut self) -> u8 {
+		if let Some(ref mut log) = self.accesses {
+			log.clear();
+		}
+		if self.halt {
+			return 0;
+		}
+		let cycles = self.emulate_op();
+		self.steps += 1;
+		self.cycles += cycles as u64;
+
+		if cycles == 0 {
+			self.halt = true;
+			println!("\n=> CPU halted\n");
+			if let Some(ref mut tracer) = self.tracer {
+				tracer.dump();
 			}
 		}
-		self.time = SystemTime::now();
+
+		if self.cycles >= self.next_int {
+			self.interrupt();
+			let (next, int_type) = Machine::interrupt_after(self.next_int);
+			self.next_int = next;
+			self.int_type = int_type;
+		}
+		cycles
+	}
+
+	pub fn is_halted(&self) -> bool {
+		self.halt
+	}
+
+	// RST number of the next interrupt and the cycles until it is due,
+	// it is only taken when interrupts are enabled then
+	pub fn next_interrupt(&self) -> (u8, u64) {
+		(self.int_type, self.next_int.saturating_sub(self.cycles))
+	}
+
+	pub fn cpu(&self) -> &CPU {
+		&self.cpu
 	}
 
-	pub fn key_pressed(&mut self, key: Key) {
-		self.io.set_key(key, 1);
+	pub fn memory(&self) -> &[u8] {
+		&self.mem
 	}
 
-	pub fn key_released(&mut self, key: Key) {
-		self.io.set_key(key, 0);
+	// e.g. to edit memory from the front end
+	pub fn write_memory(&mut self, addr: u16, value: u8) {
+		self.mem[addr as usize] = value;
+	}
+
+	fn hit_breakpoint(&mut self) -> bool {
+		let pc = self.cpu.pc;
+		if self.resume_pc.take() == Some(pc) || !self.breakpoints.contains(&pc) {
+			return false;
+		}
+		println!("=> Breakpoint at {:04X}", pc);
+		self.paused = true;
+		self.resume_pc = Some(pc);
+		if let Some(ref mut tracer) = self.tracer {
+			tracer.dump();
+		}
+		true
+	}
+
+	// traces every instruction from now on, None stops tracing
+	pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+		if let Some(ref tracer) = tracer {
+			for &pc in tracer.breakpoints() {
+				self.add_breakpoint(pc);
+			}
+		}
+		self.tracer = tracer;
+	}
+
+	// pauses before the instruction at pc is run
+	pub fn add_breakpoint(&mut self, pc: u16) {
+		if !self.breakpoints.contains(&pc) {
+			self.breakpoints.push(pc);
+		}
+	}
+
+	pub fn remove_breakpoint(&mut self, pc: u16) {
+		self.breakpoints.retain(|&b| b != pc);
+	}
+
+	pub fn breakpoints(&self) -> &[u16] {
+		&self.breakpoints
+	}
+
+	// emulated time since the start
+	pub fn cycles(&self) -> u64 {
+		self.cycles
+	}
+
+	// instructions run since the start
+	pub fn instructions(&self) -> u64 {
+		self.steps
+	}
+
+	// Records the bus accesses of every step, starting with the next one.
+	pub fn log_accesses(&mut self, enabled: bool) {
+		self.accesses = if enabled { Some(Vec::new()) } else { None };
+	}
+
+	// Reads and writes of memory and I/O ports by the last step, in order: the instruction fetch,
+	// its operands and results, and the return address pushed by an interrupt taken after it.
+	// Empty unless logging.
+	pub fn accesses(&self) -> &[Access] {
+		match self.accesses {
+			Some(ref log) => log,
+			None => &[]
+		}
+	}
+
+	pub fn set_audio(&mut self, audio: Box<dyn AudioSink>) {
+		self.io.set_audio(audio);
+	}
+
+	pub fn record_sound(&mut self, enable: bool) {
+		self.io.record_sound(enable);
+	}
+
+	pub fn sound_events(&mut self) -> Vec<SoundEvent> {
+		self.io.sound_events()
+	}
+
+	pub fn set_flip_output(&mut self, flip: Option<(u8, u8)>) {
+		self.io.set_flip_output(flip);
+	}
+
+	pub fn set_cocktail(&mut self, cocktail: bool) {
+		self.io.set_cocktail(cocktail);
+	}
+
+	// whether the game turned the screen around for player 2
+	pub fn flipped(&self) -> bool {
+		self.io.flipped()
+	}
+
+	pub fn input_pressed(&mut self, input: Input) {
+		self.io.set_input(input, true);
+	}
+
+	pub fn input_released(&mut self, input: Input) {
+		self.io.set_input(input, false);
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn access(addr: u16, value: u8, kind: AccessKind) -> Access {
+		Access {addr, value, kind}
+	}
+
+	#[test]
+	fn access_log() {
+		use bus::AccessKind::*;
+
+		let mut m = Machine::new();
+		m.load_program(&[
+			0x31, 0x00, 0x24,	// LXI SP,2400
+			0x01, 0x34, 0x12,	// LXI B,1234
+			0xC5,			// PUSH B
+			0xE3,			// XTHL
+			0xD3, 0x03,		// OUT 3
+			0x76			// HLT
+		], 0).unwrap();
+		m.step();
+		assert!(m.accesses().is_empty());
+
+		m.log_accesses(true);
+		m.step();
+		assert_eq!(m.accesses(), &[access(0x0003, 0x01, Read), access(0x0004, 0x34, Read), access(0x0005, 0x12, Read)]);
+		m.step();
+		assert_eq!(m.accesses(), &[access(0x0006, 0xC5, Read), access(0x23FF, 0x12, Write), access(0x23FE, 0x34, Write)]);
+		m.step();
+		assert_eq!(m.accesses(), &[access(0x0007, 0xE3, Read), access(0x23FE, 0x34, Read), access(0x23FF, 0x12, Read),
+			access(0x23FF, 0x00, Write), access(0x23FE, 0x00, Write)]);
+		m.step();
+		assert_eq!(m.accesses(), &[access(0x0008, 0xD3, Read), access(0x0009, 0x03, Read), access(0x0003, 0x00, IoWrite)]);
+		assert!(m.accesses()[2].is_io() && m.accesses()[2].is_write());
+
+		m.log_accesses(false);
+		m.step();
+		assert!(m.accesses().is_empty());
+	}
+
+	#[test]
+	fn interrupt_access_log() {
+		use bus::AccessKind::*;
+
+		let mut m = Machine::new();
+		m.load_program(&[
+			0x31, 0x00, 0x24,	// LXI SP,2400
+			0xFB,			// EI
+			0xC3, 0x04, 0x00	// JMP 0004
+		], 0).unwrap();
+		m.run_cycles(CYCLES_PER_FRAME / 2 - 20);
+		m.log_accesses(true);
+		// the step the interrupt comes after also logs the return address it pushes
+		while m.cpu().pc != 0x0008 {
+			m.step();
+		}
+		assert_eq!(m.accesses(), &[access(0x0004, 0xC3, Read), access(0x0005, 0x04, Read), access(0x0006, 0x00, Read),
+			access(0x23FF, 0x00, Write), access(0x23FE, 0x04, Write)]);
+	}
+
+	#[test]
+	fn interrupts_stay_in_frame() {
+		let mut m = Machine::new();
+		m.load_program(&[
+			0x31, 0x00, 0x24,	// LXI SP,2400
+			0xFB,			// EI
+			0xC3, 0x04, 0x00	// JMP 0004
+		], 0).unwrap();
+		// RST 1 and RST 2: EI, RET
+		for &addr in [0x08, 0x10].iter() {
+			m.write_memory(addr, 0xFB);
+			m.write_memory(addr + 1, 0xC9);
+		}
+
+		m.run_cycles(CYCLES_PER_FRAME / 2);
+		let (rst, due) = m.next_interrupt();
+		assert_eq!((rst, m.cycles() + due), (2, CYCLES_PER_FRAME));
+
+		for _ in 0..1000 {
+			m.run_frame();
+		}
+		let (rst, due) = m.next_interrupt();
+		assert_eq!((rst, m.cycles() + due), (1, 1000 * CYCLES_PER_FRAME + CYCLES_PER_FRAME / 2));
+		assert_eq!(m.frames(), 1000);
+	}
+
+	#[test]
+	fn stack_wrap() {
+		let mut m = Machine::new();
+		m.load_program(&[
+			0x31, 0xFE, 0xFF,	// LXI SP,FFFE
+			0x31, 0x00, 0x00,	// LXI SP,0000
+			0x21, 0xFE, 0xFF,	// LXI H,FFFE
+			0xF9,			// SPHL
+			0xC5,			// PUSH B
+			0xC1,			// POP B
+			0x31, 0x00, 0x00,	// LXI SP,0000
+			0xC5			// PUSH B
+		], 0).unwrap();
+		for _ in 0..7 {
+			m.step();
+		}
+		assert!(m.take_warnings().is_empty());
+		m.step();
+		assert_eq!(m.take_warnings().len(), 1);
 	}
 }
@@ -1,101 +1,490 @@
 #![allow(unused_mut)]
-#![allow(dead_code)]
 
+extern crate emu8080;
 extern crate image;
 extern crate piston_window;
 
-mod machine;
-mod cpu;
-mod alu;
-mod operation;
-mod disassemble;
-mod iocontroller;
-
 use piston_window::*;
 use image::RgbaImage;
-use machine::*;
+use emu8080::{synth, sound, audio, capture, trace, tracediff, vectors, WIDTH, HEIGHT};
+use emu8080::machine::*;
+use emu8080::config::*;
+use emu8080::input::{RebindMenu, Gamepads};
+use emu8080::display::Screen;
+use emu8080::artwork::Cabinet;
+use emu8080::filter::Filters;
+use emu8080::capture::Recorder;
+use emu8080::clock::FrameClock;
+use emu8080::audio::AudioBackend;
+use emu8080::memview::MemoryViewer;
+use emu8080::hud::DebugHud;
 
-const DIAG: bool = false;
-const DFIX: bool = false;
+const SCALE: f64 = 2.0;
 
-const WIDTH: u32 = 224;
-const HEIGHT: u32 = 256;
-const SCALE: u32 = 2;
+const TITLE: &str = "SpaceInvaders";
+const SCREENSHOT_DIR: &str = "screenshots";
+const RECORDING_DIR: &str = "recording";
 
 fn main() {
-	if cfg!(feature = "audio") {
-		println!("=> Audio enabled!");
+	let args: Vec<String> = std::env::args().collect();
+	if args.len() == 3 && args[1] == "--render-sounds" {
+		match synth::render_all(&args[2]) {
+			Ok(_) => println!("=> Synthesized sounds written to {}", args[2]),
+			Err(e) => println!("=> Can't write synthesized sounds: {}", e)
+		}
+		return;
 	}
-	else {
-		println!("=> Audio disabled!");
+	if args.len() == 4 && args[1] == "--record-sound" {
+		match args[2].parse::<u64>() {
+			Ok(frames) => record_sound(frames, &args[3]),
+			Err(_) => println!("=> Invalid frame count: {}", args[2])
+		}
+		return;
+	}
+	if args.len() == 4 && args[1] == "--record-video" {
+		match args[2].parse::<u64>() {
+			Ok(frames) => record_video(frames, &args[3]),
+			Err(_) => println!("=> Invalid frame count: {}", args[2])
+		}
+		return;
+	}
+
+	if args.len() == 3 && args[1] == "--benchmark" {
+		match args[2].parse::<u64>() {
+			Ok(frames) => benchmark(frames),
+			Err(_) => println!("=> Invalid frame count: {}", args[2])
+		}
+		return;
+	}
+	if args.len() == 3 && args[1] == "--test-vectors" {
+		test_vectors(&args[2]);
+		return;
+	}
+	if (args.len() == 4 || args.len() == 5) && args[1] == "--trace-diff" {
+		let offset = args.get(4).map(|a| parse_address(a)).unwrap_or(Some(0));
+		match offset {
+			Some(offset) => trace_diff(&args[2], &args[3], offset),
+			None => println!("=> Invalid load address: {}", args[4])
+		}
+		return;
 	}
 
+	let mut config = Config::load(CONFIG_FILE);
+	println!("=> Audio backend: {}", config.audio.name());
+	let mut menu = RebindMenu::new();
+	let mut pads = Gamepads::new();
+	let mut viewer = MemoryViewer::new();
+	let mut hud = DebugHud::new();
+
+	let screen = Screen::new(&config.display);
+	let mut cabinet = Cabinet::new(&config.artwork);
+	let mut filters = Filters::new(&config.filters);
 	let mut img_buffer = RgbaImage::new(WIDTH, HEIGHT);
+	let out_size = cabinet.size();
+	let scale = if out_size.0 > WIDTH { 1.0 } else { SCALE };
+	let mut fullscreen = config.view.fullscreen;
 	
-	let mut window: PistonWindow = WindowSettings::new("SpaceInvaders", [SCALE * WIDTH, SCALE * HEIGHT])
+	let mut window: PistonWindow = WindowSettings::new(TITLE, config.view.window_size(out_size, scale))
 		.exit_on_esc(true)
+		.fullscreen(fullscreen)
 		.opengl(OpenGL::V3_2)
 		.vsync(true)
 		.build()
 		.unwrap();
 
 	let ts = TextureSettings::new().filter(piston_window::Filter::Nearest);
-	let mut texture = Texture::from_image(&mut window.factory, &img_buffer, &ts).unwrap();
+	let mut texture = Texture::from_image(&mut window.factory, cabinet.compose(&img_buffer), &ts).unwrap();
+	let mut texture_size = out_size;
+	let mut viewer_texture: Option<Texture> = None;
+	let mut hud_texture: Option<Texture> = None;
+	let mut take_screenshot = false;
+	let mut recorder: Option<Recorder> = None;
 
 
 	let mut m = Machine::new();
+	m.set_audio(audio::create_sink(&config.audio));
+	// upright cabinets don't wire up the flip output
+	if config.cocktail {
+		m.set_flip_output(config.profile.flip);
+	}
+	m.set_cocktail(config.cocktail);
+	if let Some(ref trace) = config.trace {
+		match trace::Tracer::create(trace) {
+			Ok(tracer) => {
+				m.set_tracer(Some(tracer));
+				println!("=> Tracing to {}", trace.file);
+			},
+			Err(e) => println!("=> Can't create trace file {}: {}", trace.file, e)
+		}
+	}
 
 	//m.load_rom("rom/cpudiag.bin", 0x100);
 	//m.load_rom("rom/test.bin", 0x100);
-	m.load_rom("rom/invaders.bin", 0);
+	if let Err(e) = m.load_rom("rom/invaders.bin", 0) {
+		println!("=> {}", e);
+		return;
+	}
+	let mut clock = FrameClock::new();
 
 	while let Some(e) = window.next() {
 		if let Some(_) = e.render_args() {
-			update(&mut m, &mut img_buffer);
-			texture.update(&mut window.encoder, &img_buffer).unwrap();
-			window.draw_2d(&e, |_, g| {
-				clear([1.0; 4], g);
-				image(&texture, [[2./WIDTH as f64, 0., -1.], [0., 2./HEIGHT as f64, -1.]], g);
+			screen.render(m.framebuffer(), m.flipped(), &mut img_buffer);
+			let frame = cabinet.compose(filters.apply(&img_buffer));
+			if take_screenshot {
+				take_screenshot = false;
+				match capture::screenshot(frame, SCREENSHOT_DIR) {
+					Ok(file) => println!("=> Screenshot saved to {}", file),
+					Err(e) => println!("=> Can't save screenshot: {}", e)
+				}
+			}
+			if let Some(ref mut rec) = recorder {
+				if let Err(e) = rec.capture(m.cycles(), frame) {
+					println!("=> Can't write frame: {}", e);
+				}
+			}
+			// toggling filters changes the resolution of the frame
+			if frame.dimensions() != texture_size {
+				texture_size = frame.dimensions();
+				texture = Texture::from_image(&mut window.factory, frame, &ts).unwrap();
+			}
+			else {
+				texture.update(&mut window.encoder, frame).unwrap();
+			}
+			let size = window.size();
+			let transform = config.view.transform(out_size, (size.width as f64, size.height as f64))
+				.scale(out_size.0 as f64 / texture_size.0 as f64, out_size.1 as f64 / texture_size.1 as f64);
+			let (paused, speed) = (m.is_paused(), m.speed());
+			if viewer.is_open() {
+				viewer.update(m.memory());
+				let panel = viewer.render(m.memory());
+				match viewer_texture {
+					Some(ref mut t) => t.update(&mut window.encoder, panel).unwrap(),
+					None => viewer_texture = Some(Texture::from_image(&mut window.factory, panel, &ts).unwrap())
+				}
+			}
+			if hud.is_open() {
+				let panel = hud.render(&m);
+				match hud_texture {
+					Some(ref mut t) => t.update(&mut window.encoder, panel).unwrap(),
+					None => hud_texture = Some(Texture::from_image(&mut window.factory, panel, &ts).unwrap())
+				}
+			}
+			let panel_scale = (size.width / viewer.width()).max(1) as f64;
+			let viewer_open = viewer.is_open();
+			// the HUD takes up to half the width, in the top right corner
+			let hud_scale = (size.width / 2 / hud.width()).max(1);
+			let hud_x = size.width.saturating_sub(hud.width() * hud_scale) as f64;
+			let hud_scale = hud_scale as f64;
+			let hud_open = hud.is_open();
+			window.draw_2d(&e, |c, g| {
+				clear([0.0, 0.0, 0.0, 1.0], g);
+				image(&texture, transform, g);
+				draw_status(paused, speed, c.transform, g);
+				if let Some(ref t) = viewer_texture {
+					if viewer_open {
+						image(t, c.transform.scale(panel_scale, panel_scale), g);
+					}
+				}
+				if let Some(ref t) = hud_texture {
+					if hud_open {
+						image(t, c.transform.trans(hud_x, 0.0).scale(hud_scale, hud_scale), g);
+					}
+				}
 			});
 		}
 
 		if let Some(_) = e.update_args() {
-			m.run();
+			clock.run(&mut m);
+			for warning in m.take_warnings() {
+				println!("=> Warning: {}", warning);
+			}
+		}
+
+		// the key toggling the HUD isn't typed into the viewer
+		if let Some(text) = e.text_args() {
+			if !menu.is_open() && text != "`" {
+				viewer.handle_text(&text, &mut m);
+			}
 		}
 
 		if let Some(Button::Keyboard(key)) = e.press_args() {
-			m.key_pressed(key);
+			if key == Key::Tab && !menu.is_open() {
+				viewer.toggle(m.memory());
+			}
+			else if key == Key::Backquote && !menu.is_open() {
+				hud.toggle();
+			}
+			else if menu.is_open() {
+				if key == Key::Escape {
+					menu.cancel(&mut config.bindings);
+					println!("=> Rebinding cancelled");
+				}
+				else if menu.handle_key(key, &mut config.bindings) {
+					match config.save(CONFIG_FILE) {
+						Ok(_) => println!("=> Key bindings saved to {}", CONFIG_FILE),
+						Err(e) => println!("=> Can't save key bindings: {}", e)
+					}
+				}
+				window.set_exit_on_esc(!menu.is_open());
+				window.set_title(menu.prompt().unwrap_or(status_title(&m)));
+			}
+			else if viewer.handle_key(key, &mut m) {
+				// typed into the memory viewer
+			}
+			else if key == Key::F1 {
+				menu.open(&config.bindings);
+				// Esc cancels the menu instead of quitting
+				window.set_exit_on_esc(false);
+				window.set_title(menu.prompt().unwrap());
+			}
+			else if key == Key::F2 {
+				if m.is_paused() { m.resume(); } else { m.pause(); }
+				window.set_title(status_title(&m));
+			}
+			else if key == Key::F3 {
+				m.advance_frame();
+				window.set_title(status_title(&m));
+			}
+			else if key == Key::F4 {
+				let speed = config.speed.next(m.speed());
+				m.set_speed(speed);
+				window.set_title(status_title(&m));
+			}
+			else if key == Key::F5 || key == Key::F6 || key == Key::F7 || key == Key::F8 {
+				let f = &mut filters.config;
+				match key {
+					Key::F5 => f.phosphor = !f.phosphor,
+					Key::F6 => f.scanlines = !f.scanlines,
+					Key::F7 => f.bloom = !f.bloom,
+					_ => f.curvature = !f.curvature
+				}
+				// kept for the next start
+				config.filters = filters.config.clone();
+				if let Err(e) = config.save(CONFIG_FILE) {
+					println!("=> Can't save filters: {}", e);
+				}
+			}
+			else if key == Key::F9 {
+				config.view.rotate();
+			}
+			else if key == Key::F10 {
+				match recorder.take() {
+					Some(rec) => {
+						let (frames, dir) = (rec.frames(), rec.dir().to_string());
+						let events = m.sound_events();
+						m.record_sound(false);
+						match rec.finish(&events) {
+							Ok(_) => println!("=> {} frames recorded to {}", frames, dir),
+							Err(e) => println!("=> Can't write recorded audio: {}", e)
+						}
+					},
+					None => {
+						let dir = capture::recording_dir(RECORDING_DIR);
+						match Recorder::new(&dir, m.cycles(), config.audio != AudioBackend::Null) {
+							Ok(rec) => {
+								m.record_sound(true);
+								recorder = Some(rec);
+								println!("=> Recording to {}", dir);
+							},
+							Err(e) => println!("=> Can't start recording: {}", e)
+						}
+					}
+				}
+			}
+			else if key == Key::F12 {
+				take_screenshot = true;
+			}
+			else if key == Key::F11 {
+				fullscreen = !fullscreen;
+				let monitor = if fullscreen { Some(window.window.window.get_current_monitor()) } else { None };
+				window.window.window.set_fullscreen(monitor);
+			}
+			else if let Some(input) = config.bindings.get(key) {
+				m.input_pressed(input);
+			}
 		}
 
 		if let Some(Button::Keyboard(key)) = e.release_args() {
-			m.key_released(key);
+			if let Some(input) = config.bindings.get(key) {
+				m.input_released(input);
+			}
+		}
+
+		if let Some(Button::Controller(args)) = e.press_args() {
+			pads.seen(args.id as i32);
+			if let Some(input) = config.gamepad.button(args.button) {
+				m.input_pressed(input);
+			}
+		}
+
+		if let Some(Button::Controller(args)) = e.release_args() {
+			if let Some(input) = config.gamepad.button(args.button) {
+				m.input_released(input);
+			}
+		}
+
+		if let Some(args) = e.controller_axis_args() {
+			for (input, pressed) in pads.axis_moved(args.id as i32, args.axis, args.position, &config.gamepad) {
+				if pressed {
+					m.input_pressed(input);
+				}
+				else {
+					m.input_released(input);
+				}
+			}
 		}
 	}
 }
 
-fn update(machine: &mut Machine, img_buffer: &mut RgbaImage) {
-	for (n, byte) in machine.framebuffer().iter().enumerate() {
-		let i = n as u32 * 8;
+fn status_title(m: &Machine) -> String {
+	if m.is_paused() {
+		format!("{} - paused", TITLE)
+	}
+	else if m.speed() != Speed::Factor(1.0) {
+		format!("{} - {}", TITLE, m.speed().name())
+	}
+	else {
+		TITLE.to_string()
+	}
+}
 
-		let x = i / HEIGHT;
-		for shift in 0..8 {
-			let y = (i % HEIGHT) + shift as u32;
+// Pause sign, or a coloured mark when not running at real speed:
+// green when faster, red when unthrottled, blue when slower.
+fn draw_status(paused: bool, speed: Speed, transform: [[f64; 3]; 2], g: &mut G2d) {
+	let white = [1.0, 1.0, 1.0, 0.8];
+	if paused {
+		rectangle(white, [8.0, 8.0, 6.0, 20.0], transform, g);
+		rectangle(white, [18.0, 8.0, 6.0, 20.0], transform, g);
+		return;
+	}
+	let color = match speed {
+		Speed::Factor(f) if f > 1.0 => [0.0, 1.0, 0.0, 0.8],
+		Speed::Factor(f) if f < 1.0 => [0.2, 0.4, 1.0, 0.8],
+		Speed::Unthrottled => [1.0, 0.0, 0.0, 0.8],
+		_ => return
+	};
+	rectangle(color, [8.0, 8.0, 16.0, 16.0], transform, g);
+}
 
-			let color = if (byte >> shift) as u32 & 1 == 0 {
-				// off => always black
-				[0, 0, 0, 255]
-			} else if y <= 63 && (y >= 15 || y <= 15 && x >= 20 && x <= 120) {
-				// green in bottom area
-				[0, 255, 0, 255]
-			} else if y >= 200 && y <= 220 {
-				// red in UFO-area
-				[255, 0, 0, 255]
-			} else {
-				// white otherwise
-				[255; 4]
-			};
+// Runs the game without a window and writes the sound events and the rendered audio.
+fn record_sound(frames: u64, basename: &str) {
+	let mut m = Machine::new();
+	if let Err(e) = m.load_rom("rom/invaders.bin", 0) {
+		println!("=> {}", e);
+		return;
+	}
+	m.record_sound(true);
+	for _ in 0..frames {
+		m.run_frame();
+	}
+	let cycles = m.cycles();
 
-			img_buffer.put_pixel(x, y, image::Rgba(color));
+	let events = m.sound_events();
+	let log = format!("{}.log", basename);
+	let wav = format!("{}.wav", basename);
+	if let Err(e) = sound::write_log(&log, &events) {
+		println!("=> Can't write {}: {}", log, e);
+	}
+	if let Err(e) = synth::write_wav(&wav, &sound::render(&events, cycles, CPU_HZ)) {
+		println!("=> Can't write {}: {}", wav, e);
+	}
+	println!("=> {} sound events written to {} and {}", events.len(), log, wav);
+}
+
+// Runs the game without a window and writes every frame as PNG plus the audio.
+fn record_video(frames: u64, dir: &str) {
+	let config = Config::load(CONFIG_FILE);
+	let screen = Screen::new(&config.display);
+	let mut cabinet = Cabinet::new(&config.artwork);
+	let mut filters = Filters::new(&config.filters);
+	let mut img_buffer = RgbaImage::new(WIDTH, HEIGHT);
+
+	let mut m = Machine::new();
+	if let Err(e) = m.load_rom("rom/invaders.bin", 0) {
+		println!("=> {}", e);
+		return;
+	}
+	if config.cocktail {
+		m.set_flip_output(config.profile.flip);
+	}
+	m.record_sound(true);
+
+	let mut rec = match Recorder::new(dir, 0, true) {
+		Ok(rec) => rec,
+		Err(e) => {
+			println!("=> Can't start recording: {}", e);
+			return;
+		}
+	};
+	for _ in 0..frames {
+		m.run_frame();
+		screen.render(m.framebuffer(), m.flipped(), &mut img_buffer);
+		if let Err(e) = rec.capture(m.cycles(), cabinet.compose(filters.apply(&img_buffer))) {
+			println!("=> Can't write frame: {}", e);
+			return;
+		}
+	}
+
+	let events = m.sound_events();
+	match rec.finish(&events) {
+		Ok(_) => println!("=> {} frames written to {}", frames, dir),
+		Err(e) => println!("=> Can't write audio: {}", e)
+	}
+}
+
+// decimal or hex with 0x
+fn parse_address(s: &str) -> Option<usize> {
+	if s.starts_with("0x") {
+		usize::from_str_radix(&s[2..], 16).ok()
+	}
+	else {
+		s.parse().ok()
+	}
+}
+
+// Runs the ROM in lockstep with a reference log and reports the first difference.
+fn trace_diff(rom: &str, reference: &str, offset: usize) {
+	let states = match tracediff::read_reference(reference) {
+		Ok(states) => states,
+		Err(e) => {
+			println!("=> Can't read reference log {}: {}", reference, e);
+			return;
 		}
+	};
+
+	let mut m = Machine::new();
+	if let Err(e) = m.load_rom(rom, offset) {
+		println!("=> {}", e);
+		return;
+	}
+	match tracediff::compare(&mut m, &states) {
+		Ok(n) => println!("=> All {} instructions match the reference", n),
+		Err(mismatch) => print!("{}", mismatch)
+	}
+}
+
+// Runs single step test vectors and reports the pass rate of every opcode.
+fn test_vectors(path: &str) {
+	match vectors::read_vectors(path) {
+		Ok(v) => vectors::print_report(&vectors::run_all(&v)),
+		Err(e) => println!("=> Can't read test vectors {}: {}", path, e)
+	}
+}
+
+// Runs the game headless as fast as possible and reports the emulated clock rate.
+fn benchmark(frames: u64) {
+	let mut m = Machine::new();
+	if let Err(e) = m.load_rom("rom/invaders.bin", 0) {
+		println!("=> {}", e);
+		return;
+	}
+
+	let start = std::time::Instant::now();
+	for _ in 0..frames {
+		m.run_frame();
 	}
+	let elapsed = start.elapsed();
+	let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+	println!("=> {} frames ({} cycles) in {:.3}s: {:.0} MHz, {:.0}x real speed",
+		frames, m.cycles(), secs, m.cycles() as f64 / secs / 1e6, m.cycles() as f64 / secs / CPU_HZ as f64);
 }
@@ -0,0 +1,401 @@
+use piston_window::Key;
+use image::{RgbaImage, Rgba};
+
+use machine::Machine;
+use font::{draw_text, printable, clear, CHAR_W, CHAR_H, TEXT, LABEL};
+
+// bytes per row and rows shown
+const COLUMNS: u32 = 16;
+const ROWS: u32 = 32;
+// frames a changed byte stays highlighted
+const HIGHLIGHT_FRAMES: u8 = 30;
+// in characters: address, hex bytes and ASCII
+const WIDTH_CHARS: u32 = 6 + COLUMNS * 3 + 1 + COLUMNS;
+// header, rows, prompt and help
+const HEIGHT_CHARS: u32 = ROWS + 3;
+
+const CHANGED: Rgba<u8> = Rgba { data: [0xFF, 0xE0, 0x40, 0xFF] };
+const CURSOR: Rgba<u8> = Rgba { data: [0xFF, 0xFF, 0xFF, 0xFF] };
+const CURSOR_TEXT: Rgba<u8> = Rgba { data: [0, 0, 0, 0xFF] };
+
+#[derive(Debug, Clone, PartialEq)]
+enum Prompt {
+	Goto(String),
+	Search(String)
+}
+
+// Live hex and ASCII view of the memory, drawn over the game. Bytes can be
+// edited in place by typing hex digits, while running or paused.
+#[derive(Debug)]
+pub struct MemoryViewer {
+	open: bool,
+	// address of the first row shown
+	top: u32,
+	cursor: u32,
+	// high nibble typed, waiting for the low one
+	nibble: Option<u8>,
+	prompt: Option<Prompt>,
+	// last search, for finding the next match
+	pattern: Vec<u8>,
+	message: String,
+	// memory as of the last update, and frames left to highlight each byte
+	previous: Vec<u8>,
+	age: Vec<u8>,
+	image: RgbaImage
+}
+
+impl MemoryViewer {
+	pub fn new() -> MemoryViewer {
+		MemoryViewer {
+			open: false,
+			// work RAM of Invaders
+			top: 0x2000,
+			cursor: 0x2000,
+			nibble: None,
+			prompt: None,
+			pattern: Vec::new(),
+			message: String::new(),
+			previous: Vec::new(),
+			age: Vec::new(),
+			image: RgbaImage::new(WIDTH_CHARS * CHAR_W, HEIGHT_CHARS * CHAR_H)
+		}
+	}
+
+	pub fn is_open(&self) -> bool {
+		self.open
+	}
+
+	// width in pixels of the image render draws
+	pub fn width(&self) -> u32 {
+		self.image.width()
+	}
+
+	pub fn toggle(&mut self, mem: &[u8]) {
+		self.open = !self.open;
+		// changes while closed aren't highlighted
+		self.previous = mem.to_vec();
+		self.age = vec![0; mem.len()];
+		self.nibble = None;
+		self.prompt = None;
+	}
+
+	// notes the bytes changed since the last frame
+	pub fn update(&mut self, mem: &[u8]) {
+		for (i, &value) in mem.iter().enumerate() {
+			if self.previous[i] != value {
+				self.previous[i] = value;
+				self.age[i] = HIGHLIGHT_FRAMES;
+			}
+			else if self.age[i] > 0 {
+				self.age[i] -= 1;
+			}
+		}
+	}
+
+	// Moves the cursor and confirms or cancels prompts, characters come in through handle_text.
+	// Takes all keys but the function keys, so typing doesn't reach the game.
+	pub fn handle_key(&mut self, key: Key, m: &mut Machine) -> bool {
+		if !self.open || is_function_key(key) {
+			return false;
+		}
+
+		if self.prompt.is_some() {
+			match key {
+				Key::Return => {
+					let prompt = self.prompt.take();
+					self.confirm(prompt, m);
+				},
+				Key::Backspace => match self.prompt {
+					Some(Prompt::Goto(ref mut s)) | Some(Prompt::Search(ref mut s)) => { s.pop(); },
+					None => ()
+				},
+				Key::Delete => self.prompt = None,
+				_ => ()
+			}
+			return true;
+		}
+
+		let page = COLUMNS * ROWS;
+		match key {
+			Key::Left => self.move_cursor(-1),
+			Key::Right => self.move_cursor(1),
+			Key::Up => self.move_cursor(-(COLUMNS as i64)),
+			Key::Down => self.move_cursor(COLUMNS as i64),
+			Key::PageUp => self.move_cursor(-(page as i64)),
+			Key::PageDown => self.move_cursor(page as i64),
+			Key::Home => self.move_cursor(-0x10000),
+			Key::End => self.move_cursor(0x10000),
+			Key::Backspace | Key::Delete => self.nibble = None,
+			_ => ()
+		}
+		true
+	}
+
+	// typed characters: hex digits edit, g goes to an address, / searches, n finds the next match
+	pub fn handle_text(&mut self, text: &str, m: &mut Machine) {
+		if !self.open {
+			return;
+		}
+		for c in text.chars().filter(|c| !c.is_control()) {
+			match self.prompt {
+				Some(Prompt::Goto(ref mut s)) => {
+					if c.is_digit(16) && s.len() < 4 {
+						s.push(c.to_ascii_uppercase());
+					}
+					continue;
+				},
+				Some(Prompt::Search(ref mut s)) => {
+					s.push(c);
+					continue;
+				},
+				None => ()
+			}
+
+			if let Some(digit) = c.to_digit(16) {
+				self.edit(digit as u8, m);
+				continue;
+			}
+			self.nibble = None;
+			match c {
+				'g' | 'G' => self.prompt = Some(Prompt::Goto(String::new())),
+				'/' => self.prompt = Some(Prompt::Search(String::new())),
+				'n' | 'N' => {
+					let pattern = self.pattern.clone();
+					self.search(&pattern, m.memory());
+				},
+				_ => ()
+			}
+		}
+	}
+
+	fn edit(&mut self, digit: u8, m: &mut Machine) {
+		match self.nibble.take() {
+			None => self.nibble = Some(digit),
+			Some(high) => {
+				m.write_memory(self.cursor as u16, high << 4 | digit);
+				self.move_cursor(1);
+			}
+		}
+	}
+
+	fn confirm(&mut self, prompt: Option<Prompt>, m: &Machine) {
+		match prompt {
+			Some(Prompt::Goto(s)) => match u16::from_str_radix(&s, 16) {
+				Ok(addr) => {
+					self.cursor = addr as u32;
+					self.scroll();
+					self.message = String::new();
+				},
+				Err(_) => self.message = format!("Invalid address: {}", s)
+			},
+			Some(Prompt::Search(s)) => match parse_pattern(&s) {
+				Some(pattern) => {
+					self.search(&pattern, m.memory());
+					self.pattern = pattern;
+				},
+				None => self.message = format!("Invalid search: {}", s)
+			},
+			None => ()
+		}
+	}
+
+	// finds the pattern after the cursor, continuing from the start of memory at the end,
+	// but a match doesn't run over the end
+	fn search(&mut self, pattern: &[u8], mem: &[u8]) {
+		if pattern.is_empty() {
+			return;
+		}
+		let len = mem.len();
+		let found = (1..len + 1)
+			.map(|i| (self.cursor as usize + i) % len)
+			.find(|&start| mem[start..].starts_with(pattern));
+		match found {
+			Some(addr) => {
+				self.cursor = addr as u32;
+				self.scroll();
+				self.message = format!("Found at {:04X}", addr);
+			},
+			None => self.message = "Not found".to_string()
+		}
+	}
+
+	fn move_cursor(&mut self, offset: i64) {
+		self.nibble = None;
+		self.cursor = (self.cursor as i64 + offset).max(0).min(0xFFFF) as u32;
+		self.scroll();
+	}
+
+	// keeps the cursor in view
+	fn scroll(&mut self) {
+		let row = self.cursor / COLUMNS * COLUMNS;
+		if row < self.top {
+			self.top = row;
+		}
+		else if row >= self.top + COLUMNS * ROWS {
+			self.top = row + COLUMNS - COLUMNS * ROWS;
+		}
+	}
+
+	pub fn render(&mut self, mem: &[u8]) -> &RgbaImage {
+		clear(&mut self.image);
+
+		let header: String = (0..COLUMNS).map(|i| format!("{:02X} ", i)).collect();
+		let ascii: String = (0..COLUMNS).map(|i| format!("{:X}", i)).collect();
+		draw_text(&mut self.image, 6, 0, &header, LABEL, None);
+		draw_text(&mut self.image, 6 + COLUMNS * 3 + 1, 0, &ascii, LABEL, None);
+
+		for row in 0..ROWS {
+			let base = self.top + row * COLUMNS;
+			if base > 0xFFFF {
+				break;
+			}
+			draw_text(&mut self.image, 0, row + 1, &format!("{:04X}", base), LABEL, None);
+			for col in 0..COLUMNS {
+				let addr = (base + col) as usize;
+				let value = mem[addr];
+				let hex = match self.nibble {
+					Some(high) if addr as u32 == self.cursor => format!("{:X}_", high),
+					_ => format!("{:02X}", value)
+				};
+				let (fg, bg) = if addr as u32 == self.cursor {
+					(CURSOR_TEXT, Some(CURSOR))
+				}
+				else if self.age[addr] > 0 {
+					(fade(self.age[addr]), None)
+				}
+				else {
+					(TEXT, None)
+				};
+				draw_text(&mut self.image, 6 + col * 3, row + 1, &hex, fg, bg);
+				let c = printable(value as char).to_string();
+				draw_text(&mut self.image, 6 + COLUMNS * 3 + 1 + col, row + 1, &c, fg, bg);
+			}
+		}
+
+		let status = match self.prompt {
+			Some(Prompt::Goto(ref s)) => format!("Go to: {}_", s),
+			Some(Prompt::Search(ref s)) => format!("Search (hex or \"text\"): {}_", s),
+			None => format!("{:04X}: {:02X}  {}", self.cursor, mem[self.cursor as usize], self.message)
+		};
+		draw_text(&mut self.image, 0, ROWS + 1, &status, TEXT, None);
+		let help = match self.prompt {
+			Some(_) => "Return: confirm  Delete: cancel",
+			None => "0-F: edit  g: go to  /: search  n: next  Tab: close"
+		};
+		draw_text(&mut self.image, 0, ROWS + 2, help, LABEL, None);
+		&self.image
+	}
+}
+
+fn is_function_key(key: Key) -> bool {
+	match key {
+		Key::F1 | Key::F2 | Key::F3 | Key::F4 | Key::F5 | Key::F6 |
+		Key::F7 | Key::F8 | Key::F9 | Key::F10 | Key::F11 | Key::F12 => true,
+		_ => false
+	}
+}
+
+// from the highlight colour back to the text colour
+fn fade(age: u8) -> Rgba<u8> {
+	let t = age as u32;
+	let n = HIGHLIGHT_FRAMES as u32;
+	let mut color = TEXT;
+	for i in 0..3 {
+		color.data[i] = ((CHANGED.data[i] as u32 * t + TEXT.data[i] as u32 * (n - t)) / n) as u8;
+	}
+	color
+}
+
+// "text" in quotes, or hex bytes with or without spaces between them
+fn parse_pattern(s: &str) -> Option<Vec<u8>> {
+	let s = s.trim();
+	if s.starts_with('"') {
+		let text = s[1..].trim_end_matches('"');
+		return if text.is_empty() { None } else { Some(text.bytes().collect()) };
+	}
+	let digits: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+	if digits.is_empty() || digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_digit(16)) {
+		return None;
+	}
+	(0..digits.len()).step_by(2)
+		.map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+		.collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn open(m: &Machine) -> MemoryViewer {
+		let mut viewer = MemoryViewer::new();
+		viewer.toggle(m.memory());
+		viewer
+	}
+
+	#[test]
+	fn patterns() {
+		assert_eq!(parse_pattern("C3 00 01"), Some(vec![0xC3, 0x00, 0x01]));
+		assert_eq!(parse_pattern(" c30001 "), Some(vec![0xC3, 0x00, 0x01]));
+		assert_eq!(parse_pattern("\"TAITO\""), Some(b"TAITO".to_vec()));
+		assert_eq!(parse_pattern("\"TAITO"), Some(b"TAITO".to_vec()));
+		for &invalid in ["", "C3 0", "G1", "\"\""].iter() {
+			assert_eq!(parse_pattern(invalid), None, "{}", invalid);
+		}
+	}
+
+	#[test]
+	fn search() {
+		let mut m = Machine::new();
+		m.load_program(&[0xAA, 0xBB], 0x1000).unwrap();
+		m.load_program(&[0xAA, 0xBB], 0x3000).unwrap();
+		let mut viewer = open(&m);
+
+		viewer.handle_text("/AA BB", &mut m);
+		viewer.handle_key(Key::Return, &mut m);
+		assert_eq!((viewer.cursor, viewer.message.as_str()), (0x3000, "Found at 3000"));
+		// continues from the start
+		viewer.handle_text("n", &mut m);
+		assert_eq!(viewer.cursor, 0x1000);
+
+		// a match doesn't run over the end of memory
+		let mut m = Machine::new();
+		m.write_memory(0xFFFF, 0xAA);
+		m.write_memory(0x0000, 0xBB);
+		let mut viewer = open(&m);
+		viewer.handle_text("/AABB", &mut m);
+		viewer.handle_key(Key::Return, &mut m);
+		assert_eq!((viewer.cursor, viewer.message.as_str()), (0x2000, "Not found"));
+	}
+
+	#[test]
+	fn scroll() {
+		let mut m = Machine::new();
+		let mut viewer = open(&m);
+		assert_eq!((viewer.top, viewer.cursor), (0x2000, 0x2000));
+		viewer.handle_key(Key::PageDown, &mut m);
+		assert_eq!((viewer.top, viewer.cursor), (0x2010, 0x2200));
+		viewer.handle_key(Key::Up, &mut m);
+		assert_eq!((viewer.top, viewer.cursor), (0x2010, 0x21F0));
+		viewer.handle_key(Key::End, &mut m);
+		assert_eq!((viewer.top, viewer.cursor), (0xFE00, 0xFFFF));
+		viewer.handle_text("g12", &mut m);
+		viewer.handle_key(Key::Return, &mut m);
+		assert_eq!((viewer.top, viewer.cursor), (0x0010, 0x0012));
+	}
+
+	#[test]
+	fn edit() {
+		let mut m = Machine::new();
+		let mut viewer = open(&m);
+		viewer.handle_text("4", &mut m);
+		assert_eq!((m.memory()[0x2000], viewer.nibble), (0x00, Some(4)));
+		viewer.handle_text("2a", &mut m);
+		assert_eq!((m.memory()[0x2000], m.memory()[0x2001], viewer.cursor), (0x42, 0x00, 0x2001));
+		// moving drops the first digit
+		viewer.handle_key(Key::Right, &mut m);
+		viewer.handle_text("5", &mut m);
+		viewer.handle_text("5", &mut m);
+		assert_eq!((m.memory()[0x2001], m.memory()[0x2002]), (0x00, 0x55));
+	}
+}
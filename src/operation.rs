@@ -1,6 +1,6 @@
-use std;
 use cpu::CPU;
 use alu::ALU;
+use bus::Bus;
 use std::mem::swap;
 
 use DIAG;
@@ -18,21 +18,19 @@ macro_rules! splitr {
 	($x:expr) => (($x) as u8)
 }
 
-pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8, arg1: u8, arg2: u8) -> u8 {
+pub fn execute<B: Bus>(cpu: &mut CPU, alu: &mut ALU, mem: &mut B, opcode: u8, arg1: u8, arg2: u8) -> u8 {
 	macro_rules! push {
 		($h:expr, $l:expr) => ({
-			let sp_addr = cpu.sp as usize;
-			mem[sp_addr - 1] = $h;
-			mem[sp_addr - 2] = $l;
+			mem.write(cpu.sp.wrapping_sub(1), $h);
+			mem.write(cpu.sp.wrapping_sub(2), $l);
 			cpu.sp = cpu.sp.wrapping_sub(2);
 		})
 	}
 
 	macro_rules! pop {
 		($h:expr, $l:expr) => ({
-			let sp_addr = cpu.sp as usize;
-			$l = mem[sp_addr];
-			$h = mem[sp_addr + 1];
+			$l = mem.read(cpu.sp);
+			$h = mem.read(cpu.sp.wrapping_add(1));
 			cpu.sp = cpu.sp.wrapping_add(2);
 		})
 	}
@@ -84,8 +82,8 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 
 	macro_rules! ret {
 		() => ({
-			let sp_addr = cpu.sp as usize;
-			cpu.pc = append!(mem[sp_addr + 1], mem[sp_addr]);
+			let lo = mem.read(cpu.sp);
+			cpu.pc = append!(mem.read(cpu.sp.wrapping_add(1)), lo);
 			cpu.sp = cpu.sp.wrapping_add(2);
 		})
 	}
@@ -96,63 +94,63 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 	let inc = match opcode {
 		0x00 => 0,		// NOP
 		0x01 => {cpu.c = arg1; cpu.b = arg2; 2},
-		0x02 => {mem[cpu.get_bc() as usize] = cpu.a; 0},
+		0x02 => {mem.write(cpu.get_bc(), cpu.a); 0},
 		0x03 => {let res = cpu.get_bc().wrapping_add(1); cpu.set_bc(res); 0},
 		0x04 => {cpu.b = alu.add(cpu.b, 1); cpu.cc.set_zsp(cpu.b, &alu); 0},
 		0x05 => {cpu.b = alu.sub(cpu.b, 1); cpu.cc.set_zsp(cpu.b, &alu); 0},
 		0x06 => {cpu.b = arg1; 1},
-		0x07 => {cpu.a = cpu.a.rotate_left(1); cpu.cc.cy = cpu.a & 1; 0},
+		0x07 => {cpu.a = cpu.a.rotate_left(1); cpu.cc.set_cy(cpu.a & 1); 0},
 		0x08 => 0,		// NOP
 		0x09 => {
 			let res = alu.addx(cpu.get_hl(), cpu.get_bc());
 			cpu.set_hl(res);
-			cpu.cc.cy = alu.cy as u8;
+			cpu.cc.set_cy(alu.cy as u8);
 			0
 		},
-		0x0A => {cpu.a = mem[cpu.get_bc() as usize]; 0},
+		0x0A => {cpu.a = mem.read(cpu.get_bc()); 0},
 		0x0B => {let res = cpu.get_bc().wrapping_sub(1); cpu.set_bc(res); 0},
 		0x0C => {cpu.c = alu.add(cpu.c, 1); cpu.cc.set_zsp(cpu.c, &alu); 0},
 		0x0D => {cpu.c = alu.sub(cpu.c, 1); cpu.cc.set_zsp(cpu.c, &alu); 0},
 		0x0E => {cpu.c = arg1; 1},
-		0x0F => {cpu.a = cpu.a.rotate_right(1); cpu.cc.cy = (cpu.a & 0x80) >> 7; 0},
+		0x0F => {cpu.a = cpu.a.rotate_right(1); cpu.cc.set_cy((cpu.a & 0x80) >> 7); 0},
 
 		0x10 => 0,		// NOP
 		0x11 => {cpu.e = arg1; cpu.d = arg2; 2},
-		0x12 => {mem[cpu.get_de() as usize] = cpu.a; 0},
+		0x12 => {mem.write(cpu.get_de(), cpu.a); 0},
 		0x13 => {let res = cpu.get_de().wrapping_add(1); cpu.set_de(res); 0},
 		0x14 => {cpu.d = alu.add(cpu.d, 1); cpu.cc.set_zsp(cpu.d, &alu); 0},
 		0x15 => {cpu.d = alu.sub(cpu.d, 1); cpu.cc.set_zsp(cpu.d, &alu); 0},
 		0x16 => {cpu.d = arg1; 1},
 		0x17 => {
 			let x = cpu.a;
-			cpu.a = cpu.cc.cy | (x << 1);
-			cpu.cc.cy = (x & 0x80) >> 7;
+			cpu.a = cpu.cc.cy() | (x << 1);
+			cpu.cc.set_cy((x & 0x80) >> 7);
 			0
 		},
 		0x18 => 0,		// NOP
 		0x19 => {
 			let res = alu.addx(cpu.get_hl(), cpu.get_de());
 			cpu.set_hl(res);
-			cpu.cc.cy = alu.cy as u8;
+			cpu.cc.set_cy(alu.cy as u8);
 			0
 		},
-		0x1A => {cpu.a = mem[cpu.get_de() as usize]; 0},
+		0x1A => {cpu.a = mem.read(cpu.get_de()); 0},
 		0x1B => {let res = cpu.get_de().wrapping_sub(1); cpu.set_de(res); 0},
 		0x1C => {cpu.e = alu.add(cpu.e, 1); cpu.cc.set_zsp(cpu.e, &alu); 0},
 		0x1D => {cpu.e = alu.sub(cpu.e, 1); cpu.cc.set_zsp(cpu.e, &alu); 0},
 		0x1E => {cpu.e = arg1; 1},
 		0x1F => {
 			let x = cpu.a;
-			cpu.a = (cpu.cc.cy << 7) | (x >> 1);
-			cpu.cc.cy = x & 1;
+			cpu.a = (cpu.cc.cy() << 7) | (x >> 1);
+			cpu.cc.set_cy(x & 1);
 			0
 		},
 
 		0x20 => 0,		// NOP
 		0x21 => {cpu.l = arg1; cpu.h = arg2; 2},
 		0x22 => {
-			mem[args as usize] = cpu.l;
-			mem[args as usize + 1] = cpu.h;
+			mem.write(args, cpu.l);
+			mem.write(args.wrapping_add(1), cpu.h);
 			2
 		},
 		0x23 => {let res = cpu.get_hl().wrapping_add(1); cpu.set_hl(res); 0},
@@ -160,13 +158,7 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x25 => {cpu.h = alu.sub(cpu.h, 1); cpu.cc.set_zsp(cpu.h, &alu); 0},
 		0x26 => {cpu.h = arg1; 1},
 		0x27 => {
-			if (cpu.a & 0x0F) > 9 || cpu.cc.ac == 1 {
-				cpu.a = alu.add(cpu.a, 6);
-			}
-			cpu.cc.ac = alu.ac as u8;
-			if (cpu.a & 0xF0) > 0x90 || cpu.cc.cy == 1 {
-				cpu.a = alu.add(cpu.a, 96);
-			}
+			cpu.a = alu.daa(cpu.a, cpu.cc.cy() == 1, cpu.cc.ac() == 1);
 			cpu.cc.set_arith(cpu.a, &alu);
 			0
 		},
@@ -174,12 +166,12 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x29 => {
 			let res = alu.addx(cpu.get_hl(), cpu.get_hl());
 			cpu.set_hl(res);
-			cpu.cc.cy = alu.cy as u8;
+			cpu.cc.set_cy(alu.cy as u8);
 			0
 		},
 		0x2A => {
-			cpu.l = mem[args as usize];
-			cpu.h = mem[args as usize + 1];
+			cpu.l = mem.read(args);
+			cpu.h = mem.read(args.wrapping_add(1));
 			2
 		},
 		0x2B => {let res = cpu.get_hl().wrapping_sub(1); cpu.set_hl(res); 0},
@@ -190,35 +182,35 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 
 		0x30 => 0,		// NOP
 		0x31 => {cpu.sp = args; 2},
-		0x32 => {mem[args as usize] = cpu.a; 2},
+		0x32 => {mem.write(args, cpu.a); 2},
 		0x33 => {cpu.sp = cpu.sp.wrapping_add(1); 0},
 		0x34 => {
-			let val = alu.add(cpu.from_hl(&mut mem), 1);
+			let val = alu.add(cpu.from_hl(mem), 1);
 			cpu.cc.set_zsp(val, &alu);
-			cpu.to_hl(&mut mem, val);
+			cpu.to_hl(mem, val);
 			0
 		},
 		0x35 => {
-			let val = alu.sub(cpu.from_hl(&mut mem), 1);
+			let val = alu.sub(cpu.from_hl(mem), 1);
 			cpu.cc.set_zsp(val, &alu);
-			cpu.to_hl(&mut mem, val);
+			cpu.to_hl(mem, val);
 			0
 		},
-		0x36 => {cpu.to_hl(&mut mem, arg1); 1},
-		0x37 => {cpu.cc.cy = 1; 0},
+		0x36 => {cpu.to_hl(mem, arg1); 1},
+		0x37 => {cpu.cc.set_cy(1); 0},
 		0x38 => 0,		// NOP
 		0x39 => {
 			let res = alu.addx(cpu.get_hl(), cpu.sp);
 			cpu.set_hl(res);
-			cpu.cc.cy = alu.cy as u8;
+			cpu.cc.set_cy(alu.cy as u8);
 			0
 		},
-		0x3A => {cpu.a = mem[args as usize]; 2},
+		0x3A => {cpu.a = mem.read(args); 2},
 		0x3B => {cpu.sp = cpu.sp.wrapping_sub(1); 0},
 		0x3C => {cpu.a = alu.add(cpu.a, 1); cpu.cc.set_zsp(cpu.a, &alu); 0},
 		0x3D => {cpu.a = alu.sub(cpu.a, 1); cpu.cc.set_zsp(cpu.a, &alu); 0},
 		0x3E => {cpu.a = arg1; 1},
-		0x3F => {cpu.cc.cy = 0; 0},
+		0x3F => {let cy = cpu.cc.cy() ^ 1; cpu.cc.set_cy(cy); 0},
 
 		0x40 => {cpu.b = cpu.b; 0},
 		0x41 => {cpu.b = cpu.c; 0},
@@ -226,7 +218,7 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x43 => {cpu.b = cpu.e; 0},
 		0x44 => {cpu.b = cpu.h; 0},
 		0x45 => {cpu.b = cpu.l; 0},
-		0x46 => {cpu.b = cpu.from_hl(&mem); 0},
+		0x46 => {cpu.b = cpu.from_hl(mem); 0},
 		0x47 => {cpu.b = cpu.a; 0},
 		0x48 => {cpu.c = cpu.b; 0},
 		0x49 => {cpu.c = cpu.c; 0},
@@ -234,7 +226,7 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x4B => {cpu.c = cpu.e; 0},
 		0x4C => {cpu.c = cpu.h; 0},
 		0x4D => {cpu.c = cpu.l; 0},
-		0x4E => {cpu.c = cpu.from_hl(&mem); 0},
+		0x4E => {cpu.c = cpu.from_hl(mem); 0},
 		0x4F => {cpu.c = cpu.a; 0},
 
 		0x50 => {cpu.d = cpu.b; 0},
@@ -243,7 +235,7 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x53 => {cpu.d = cpu.e; 0},
 		0x54 => {cpu.d = cpu.h; 0},
 		0x55 => {cpu.d = cpu.l; 0},
-		0x56 => {cpu.d = cpu.from_hl(&mem); 0},
+		0x56 => {cpu.d = cpu.from_hl(mem); 0},
 		0x57 => {cpu.d = cpu.a; 0},
 		0x58 => {cpu.e = cpu.b; 0},
 		0x59 => {cpu.e = cpu.c; 0},
@@ -251,7 +243,7 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x5B => {cpu.e = cpu.e; 0},
 		0x5C => {cpu.e = cpu.h; 0},
 		0x5D => {cpu.e = cpu.l; 0},
-		0x5E => {cpu.e = cpu.from_hl(&mem); 0},
+		0x5E => {cpu.e = cpu.from_hl(mem); 0},
 		0x5F => {cpu.e = cpu.a; 0},
 
 		0x60 => {cpu.h = cpu.b; 0},
@@ -260,7 +252,7 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x63 => {cpu.h = cpu.e; 0},
 		0x64 => {cpu.h = cpu.h; 0},
 		0x65 => {cpu.h = cpu.l; 0},
-		0x66 => {cpu.h = cpu.from_hl(&mem); 0},
+		0x66 => {cpu.h = cpu.from_hl(mem); 0},
 		0x67 => {cpu.h = cpu.a; 0},
 		0x68 => {cpu.l = cpu.b; 0},
 		0x69 => {cpu.l = cpu.c; 0},
@@ -268,24 +260,24 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x6B => {cpu.l = cpu.e; 0},
 		0x6C => {cpu.l = cpu.h; 0},
 		0x6D => {cpu.l = cpu.l; 0},
-		0x6E => {cpu.l = cpu.from_hl(&mem); 0},
+		0x6E => {cpu.l = cpu.from_hl(mem); 0},
 		0x6F => {cpu.l = cpu.a; 0},
 
-		0x70 => {cpu.to_hl(&mut mem, cpu.b); 0},
-		0x71 => {cpu.to_hl(&mut mem, cpu.c); 0},
-		0x72 => {cpu.to_hl(&mut mem, cpu.d); 0},
-		0x73 => {cpu.to_hl(&mut mem, cpu.e); 0},
-		0x74 => {cpu.to_hl(&mut mem, cpu.h); 0},
-		0x75 => {cpu.to_hl(&mut mem, cpu.l); 0},
+		0x70 => {cpu.to_hl(mem, cpu.b); 0},
+		0x71 => {cpu.to_hl(mem, cpu.c); 0},
+		0x72 => {cpu.to_hl(mem, cpu.d); 0},
+		0x73 => {cpu.to_hl(mem, cpu.e); 0},
+		0x74 => {cpu.to_hl(mem, cpu.h); 0},
+		0x75 => {cpu.to_hl(mem, cpu.l); 0},
 		0x76 => {return 0;}, // HLT
-		0x77 => {cpu.to_hl(&mut mem, cpu.a); 0},
+		0x77 => {cpu.to_hl(mem, cpu.a); 0},
 		0x78 => {cpu.a = cpu.b; 0},
 		0x79 => {cpu.a = cpu.c; 0},
 		0x7A => {cpu.a = cpu.d; 0},
 		0x7B => {cpu.a = cpu.e; 0},
 		0x7C => {cpu.a = cpu.h; 0},
 		0x7D => {cpu.a = cpu.l; 0},
-		0x7E => {cpu.a = cpu.from_hl(&mem); 0},
+		0x7E => {cpu.a = cpu.from_hl(mem); 0},
 		0x7F => {cpu.a = cpu.a; 0},
 
 		0x80 => {add!(cpu.a, cpu.b); 0},
@@ -294,16 +286,16 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x83 => {add!(cpu.a, cpu.e); 0},
 		0x84 => {add!(cpu.a, cpu.h); 0},
 		0x85 => {add!(cpu.a, cpu.l); 0},
-		0x86 => {add!(cpu.a, cpu.from_hl(&mem)); 0},
+		0x86 => {add!(cpu.a, cpu.from_hl(mem)); 0},
 		0x87 => {add!(cpu.a, cpu.a); 0},
-		0x88 => {add!(cpu.a, cpu.b, cpu.cc.cy); 0},
-		0x89 => {add!(cpu.a, cpu.c, cpu.cc.cy); 0},
-		0x8A => {add!(cpu.a, cpu.d, cpu.cc.cy); 0},
-		0x8B => {add!(cpu.a, cpu.e, cpu.cc.cy); 0},
-		0x8C => {add!(cpu.a, cpu.h, cpu.cc.cy); 0},
-		0x8D => {add!(cpu.a, cpu.l, cpu.cc.cy); 0},
-		0x8E => {add!(cpu.a, cpu.from_hl(&mem), cpu.cc.cy); 0},
-		0x8F => {add!(cpu.a, cpu.a, cpu.cc.cy); 0},
+		0x88 => {add!(cpu.a, cpu.b, cpu.cc.cy()); 0},
+		0x89 => {add!(cpu.a, cpu.c, cpu.cc.cy()); 0},
+		0x8A => {add!(cpu.a, cpu.d, cpu.cc.cy()); 0},
+		0x8B => {add!(cpu.a, cpu.e, cpu.cc.cy()); 0},
+		0x8C => {add!(cpu.a, cpu.h, cpu.cc.cy()); 0},
+		0x8D => {add!(cpu.a, cpu.l, cpu.cc.cy()); 0},
+		0x8E => {add!(cpu.a, cpu.from_hl(mem), cpu.cc.cy()); 0},
+		0x8F => {add!(cpu.a, cpu.a, cpu.cc.cy()); 0},
 
 		0x90 => {sub!(cpu.a, cpu.b); 0},
 		0x91 => {sub!(cpu.a, cpu.c); 0},
@@ -311,16 +303,16 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0x93 => {sub!(cpu.a, cpu.e); 0},
 		0x94 => {sub!(cpu.a, cpu.h); 0},
 		0x95 => {sub!(cpu.a, cpu.l); 0},
-		0x96 => {sub!(cpu.a, cpu.from_hl(&mem)); 0},
+		0x96 => {sub!(cpu.a, cpu.from_hl(mem)); 0},
 		0x97 => {sub!(cpu.a, cpu.a); 0},
-		0x98 => {sub!(cpu.a, cpu.b, cpu.cc.cy); 0},
-		0x99 => {sub!(cpu.a, cpu.c, cpu.cc.cy); 0},
-		0x9A => {sub!(cpu.a, cpu.d, cpu.cc.cy); 0},
-		0x9B => {sub!(cpu.a, cpu.e, cpu.cc.cy); 0},
-		0x9C => {sub!(cpu.a, cpu.h, cpu.cc.cy); 0},
-		0x9D => {sub!(cpu.a, cpu.l, cpu.cc.cy); 0},
-		0x9E => {sub!(cpu.a, cpu.from_hl(&mem), cpu.cc.cy); 0},
-		0x9F => {sub!(cpu.a, cpu.a, cpu.cc.cy); 0},
+		0x98 => {sub!(cpu.a, cpu.b, cpu.cc.cy()); 0},
+		0x99 => {sub!(cpu.a, cpu.c, cpu.cc.cy()); 0},
+		0x9A => {sub!(cpu.a, cpu.d, cpu.cc.cy()); 0},
+		0x9B => {sub!(cpu.a, cpu.e, cpu.cc.cy()); 0},
+		0x9C => {sub!(cpu.a, cpu.h, cpu.cc.cy()); 0},
+		0x9D => {sub!(cpu.a, cpu.l, cpu.cc.cy()); 0},
+		0x9E => {sub!(cpu.a, cpu.from_hl(mem), cpu.cc.cy()); 0},
+		0x9F => {sub!(cpu.a, cpu.a, cpu.cc.cy()); 0},
 
 		0xA0 => {cpu.a &= cpu.b; cpu.cc.set_logic(cpu.a); 0},
 		0xA1 => {cpu.a &= cpu.c; cpu.cc.set_logic(cpu.a); 0},
@@ -328,7 +320,7 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0xA3 => {cpu.a &= cpu.e; cpu.cc.set_logic(cpu.a); 0},
 		0xA4 => {cpu.a &= cpu.h; cpu.cc.set_logic(cpu.a); 0},
 		0xA5 => {cpu.a &= cpu.l; cpu.cc.set_logic(cpu.a); 0},
-		0xA6 => {cpu.a &= cpu.from_hl(&mem); cpu.cc.set_logic(cpu.a); 0},
+		0xA6 => {cpu.a &= cpu.from_hl(mem); cpu.cc.set_logic(cpu.a); 0},
 		0xA7 => {cpu.a &= cpu.a; cpu.cc.set_logic(cpu.a); 0},
 		0xA8 => {cpu.a ^= cpu.b; cpu.cc.set_logic(cpu.a); 0},
 		0xA9 => {cpu.a ^= cpu.c; cpu.cc.set_logic(cpu.a); 0},
@@ -336,7 +328,7 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0xAB => {cpu.a ^= cpu.e; cpu.cc.set_logic(cpu.a); 0},
 		0xAC => {cpu.a ^= cpu.h; cpu.cc.set_logic(cpu.a); 0},
 		0xAD => {cpu.a ^= cpu.l; cpu.cc.set_logic(cpu.a); 0},
-		0xAE => {cpu.a ^= cpu.from_hl(&mem); cpu.cc.set_logic(cpu.a); 0},
+		0xAE => {cpu.a ^= cpu.from_hl(mem); cpu.cc.set_logic(cpu.a); 0},
 		0xAF => {cpu.a ^= cpu.a; cpu.cc.set_logic(cpu.a); 0},
 
 		0xB0 => {cpu.a |= cpu.b; cpu.cc.set_logic(cpu.a); 0},
@@ -345,7 +337,7 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0xB3 => {cpu.a |= cpu.e; cpu.cc.set_logic(cpu.a); 0},
 		0xB4 => {cpu.a |= cpu.h; cpu.cc.set_logic(cpu.a); 0},
 		0xB5 => {cpu.a |= cpu.l; cpu.cc.set_logic(cpu.a); 0},
-		0xB6 => {cpu.a |= cpu.from_hl(&mem); cpu.cc.set_logic(cpu.a); 0},
+		0xB6 => {cpu.a |= cpu.from_hl(mem); cpu.cc.set_logic(cpu.a); 0},
 		0xB7 => {cpu.a |= cpu.a; cpu.cc.set_logic(cpu.a); 0},
 		0xB8 => {cmp!(cpu.a, cpu.b); 0},
 		0xB9 => {cmp!(cpu.a, cpu.c); 0},
@@ -353,50 +345,50 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 		0xBB => {cmp!(cpu.a, cpu.e); 0},
 		0xBC => {cmp!(cpu.a, cpu.h); 0},
 		0xBD => {cmp!(cpu.a, cpu.l); 0},
-		0xBE => {cmp!(cpu.a, cpu.from_hl(&mem)); 0},
+		0xBE => {cmp!(cpu.a, cpu.from_hl(mem)); 0},
 		0xBF => {cmp!(cpu.a, cpu.a); 0},
 
-		0xC0 => {if cpu.cc.z == 0 {ret!();} 0},
+		0xC0 => {if cpu.cc.z() == 0 {ret!();} 0},
 		0xC1 => {pop!(cpu.b, cpu.c); 0},
 		0xC2 => {
-			if cpu.cc.z == 0 {cpu.pc = args; 0}
+			if cpu.cc.z() == 0 {cpu.pc = args; 0}
 			else {2}
 		},
 		0xC3 => {cpu.pc = args; 0},
 		0xC4 => {
-			if cpu.cc.z == 0 {callx!(args); 0}
+			if cpu.cc.z() == 0 {callx!(args); 0}
 			else {2}
 		},
 		0xC5 => {push!(cpu.b, cpu.c); 0},
 		0xC6 => {add!(cpu.a, arg1); 1},
 		0xC7 => {call!(0); 0},
-		0xC8 => {if cpu.cc.z == 1 {ret!();} 0},
+		0xC8 => {if cpu.cc.z() == 1 {ret!();} 0},
 		0xC9 => {ret!(); 0},
 		0xCA => {
-			if cpu.cc.z == 1 {cpu.pc = args; 0}
+			if cpu.cc.z() == 1 {cpu.pc = args; 0}
 			else {2}
 		},
 		0xCB => {cpu.pc = args; 0},
 		0xCC => {
-			if cpu.cc.z == 1 {callx!(args); 0}
+			if cpu.cc.z() == 1 {callx!(args); 0}
 			else {2}
 		},
 		0xCD | 0xDD | 0xED | 0xFD => {
 			if DIAG && args == 5 {
 				// BDOS syscall -> print
 				if cpu.c == 9 {
-					let mut addr = cpu.get_de() as usize;
+					let mut addr = cpu.get_de();
 					if DFIX {
-						addr += 1;
+						addr = addr.wrapping_add(1);
 					}
-					loop {
-						let d = mem[addr] as u32;
-						let c = std::char::from_u32(d).expect("invalid char");
+					// stops at the end of memory if the string isn't terminated
+					for _ in 0..0x10000 {
+						let c = mem.read(addr) as char;
 						if c == '$' {
 							break;
 						}
 						print!("{}", c);
-						addr += 1;
+						addr = addr.wrapping_add(1);
 					}
 				}
 				else if cpu.c == 2 {print!("{:04X}", cpu.get_hl());}
@@ -404,61 +396,63 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 			}
 			else {callx!(args); 0}
 		},
-		0xCE => {add!(cpu.a, arg1, cpu.cc.cy); 1},
+		0xCE => {add!(cpu.a, arg1, cpu.cc.cy()); 1},
 		0xCF => {call!(8); 0},
 
-		0xD0 => {if cpu.cc.cy == 0 {ret!();} 0},
+		0xD0 => {if cpu.cc.cy() == 0 {ret!();} 0},
 		0xD1 => {pop!(cpu.d, cpu.e); 0},
 		0xD2 => {
-			if cpu.cc.cy == 0 {cpu.pc = args; 0}
+			if cpu.cc.cy() == 0 {cpu.pc = args; 0}
 			else {2}
 		},
 		0xD3 => 1, //{println!("=> OUT: {:#04X}", arg1); 1},
 		0xD4 => {
-			if cpu.cc.cy == 0 {callx!(args); 0}
+			if cpu.cc.cy() == 0 {callx!(args); 0}
 			else {2}
 		},
 		0xD5 => {push!(cpu.d, cpu.e); 0},
 		0xD6 => {sub!(cpu.a, arg1); 1},
 		0xD7 => {call!(0x10); 0},
-		0xD8 => {if cpu.cc.cy == 1 {ret!();} 0},
+		0xD8 => {if cpu.cc.cy() == 1 {ret!();} 0},
 		0xD9 => {ret!(); 0},
 		0xDA => {
-			if cpu.cc.cy == 1 {cpu.pc = args; 0}
+			if cpu.cc.cy() == 1 {cpu.pc = args; 0}
 			else {2}
 		},
 		0xDB => 1, //{println!("=> IN: {:#04X}", arg1); 1},
 		0xDC => {
-			if cpu.cc.cy == 1 {callx!(args); 0}
+			if cpu.cc.cy() == 1 {callx!(args); 0}
 			else {2}
 		},
 		//0xDD -> 0xCD
-		0xDE => {sub!(cpu.a, arg1, cpu.cc.cy); 1},
+		0xDE => {sub!(cpu.a, arg1, cpu.cc.cy()); 1},
 		0xDF => {call!(0x18); 0},
 
-		0xE0 => {if cpu.cc.p == 0 {ret!();} 0},
+		0xE0 => {if cpu.cc.p() == 0 {ret!();} 0},
 		0xE1 => {pop!(cpu.h, cpu.l); 0},
 		0xE2 => {
-			if cpu.cc.p == 0 {cpu.pc = args; 0}
+			if cpu.cc.p() == 0 {cpu.pc = args; 0}
 			else {2}
 		},
 		0xE3 => {
-			let sp_addr = cpu.sp as usize;
-			swap(&mut cpu.l, &mut mem[sp_addr]);
-			swap(&mut cpu.h, &mut mem[sp_addr + 1]);
+			let (l, h) = (mem.read(cpu.sp), mem.read(cpu.sp.wrapping_add(1)));
+			mem.write(cpu.sp.wrapping_add(1), cpu.h);
+			mem.write(cpu.sp, cpu.l);
+			cpu.l = l;
+			cpu.h = h;
 			0
 		},
 		0xE4 => {
-			if cpu.cc.p == 0 {callx!(args); 0}
+			if cpu.cc.p() == 0 {callx!(args); 0}
 			else {2}
 		},
 		0xE5 => {push!(cpu.h, cpu.l); 0},
 		0xE6 => {cpu.a &= arg1; cpu.cc.set_logic(cpu.a); 1},
 		0xE7 => {call!(0x20); 0},
-		0xE8 => {if cpu.cc.p == 1 {ret!();} 0},
+		0xE8 => {if cpu.cc.p() == 1 {ret!();} 0},
 		0xE9 => {cpu.pc = cpu.get_hl(); 0},
 		0xEA => {
-			if cpu.cc.p == 1 {cpu.pc = args; 0}
+			if cpu.cc.p() == 1 {cpu.pc = args; 0}
 			else {2}
 		},
 		0xEB => {
@@ -467,70 +461,56 @@ pub fn execute(cpu: &mut CPU, alu: &mut ALU, mut mem: &mut Vec<u8>, opcode: u8,
 			0
 		},
 		0xEC => {
-			if cpu.cc.p == 1 {callx!(args); 0}
+			if cpu.cc.p() == 1 {callx!(args); 0}
 			else {2}
 		},
 		//0xED -> 0xCD
-		0xEE => {
-			cpu.a ^= arg1;
-			cpu.cc.set_zsp(cpu.a, &alu);
-			cpu.cc.cy = 0;
-			1
-		},
+		0xEE => {cpu.a ^= arg1; cpu.cc.set_logic(cpu.a); 1},
 		0xEF => {call!(0x28); 0},
 
-		0xF0 => {if cpu.cc.s == 0 {ret!();} 0},
+		0xF0 => {if cpu.cc.s() == 0 {ret!();} 0},
 		0xF1 => {
-			let sp_addr = cpu.sp as usize;
-			cpu.cc.set_psw(mem[sp_addr]);
-			cpu.a = mem[sp_addr + 1];
+			cpu.cc.set_psw(mem.read(cpu.sp));
+			cpu.a = mem.read(cpu.sp.wrapping_add(1));
 			cpu.sp = cpu.sp.wrapping_add(2);
 			0
 		},
 		0xF2 => {
-			if cpu.cc.s == 0 {cpu.pc = args; 0}
+			if cpu.cc.s() == 0 {cpu.pc = args; 0}
 			else {2}
 		},
 		0xF3 => {cpu.int_enable = 0; 0},
 		0xF4 => {
-			if cpu.cc.s == 0 {callx!(args); 0}
+			if cpu.cc.s() == 0 {callx!(args); 0}
 			else {2}
 		},
 		0xF5 => {push!(cpu.a, cpu.cc.get_psw()); 0},
-		0xF6 => {
-			cpu.a |= arg1;
-			cpu.cc.set_zsp(cpu.a, &alu);
-			cpu.cc.cy = 0;
-			1
-		},
+		0xF6 => {cpu.a |= arg1; cpu.cc.set_logic(cpu.a); 1},
 		0xF7 => {call!(0x30); 0},
-		0xF8 => {if cpu.cc.s == 1 {ret!();} 0},
+		0xF8 => {if cpu.cc.s() == 1 {ret!();} 0},
 		0xF9 => {cpu.sp = cpu.get_hl(); 0},
 		0xFA => {
-			if cpu.cc.s != 0 {cpu.pc = args; 0}
+			if cpu.cc.s() != 0 {cpu.pc = args; 0}
 			else {2}
 		},
 		0xFB => {cpu.int_enable = 1; 0},
 		0xFC => {
-			if cpu.cc.s == 1 {callx!(args); 0}
+			if cpu.cc.s() == 1 {callx!(args); 0}
 			else {2}
 		},
 		//0xFD -> 0xCD
 		0xFE => {cmp!(cpu.a, arg1); 1},
-		0xFF => {call!(0x38); 0},
-
-		_ => unreachable!()
+		0xFF => {call!(0x38); 0}
 	};
 
 	cpu.pc = cpu.pc.wrapping_add(inc);
 	CYCLES8080[opcode as usize]
 }
 
-pub fn generate_interrupt(cpu: &mut CPU, mem: &mut Vec<u8>, interrupt_num: u8) {
+pub fn generate_interrupt<B: Bus>(cpu: &mut CPU, mem: &mut B, interrupt_num: u8) {
 	//This is identical to an "RST interrupt_num" instruction.    
-	let sp_addr = cpu.sp as usize;
-	mem[sp_addr - 1] = splitl!(cpu.pc);
-	mem[sp_addr - 2] = splitr!(cpu.pc);
+	mem.write(cpu.sp.wrapping_sub(1), splitl!(cpu.pc));
+	mem.write(cpu.sp.wrapping_sub(2), splitr!(cpu.pc));
 	cpu.sp = cpu.sp.wrapping_sub(2);
 	cpu.pc = 8 * interrupt_num as u16;
 	cpu.int_enable = 0;
@@ -557,3 +537,590 @@ static CYCLES8080: [u8; 0x100] = [
 	11, 10, 10, 18, 17, 11, 7, 11, 11, 5, 10, 5, 17, 17, 7, 11, 
 	11, 10, 10, 4, 17, 11, 7, 11, 11, 5, 10, 4, 17, 17, 7, 11
 ];
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use cpu::{FLAG_S, FLAG_Z, FLAG_AC, FLAG_P, FLAG_CY};
+	use disassemble::instr_len;
+
+	const PC: u16 = 0x1000;
+	const SP: u16 = 0x2000;
+	const HL: u16 = 0x3089;
+	// arguments of every instruction, 0x5678 as an address
+	const ARG1: u8 = 0x78;
+	const ARG2: u8 = 0x56;
+	const ARGS: u16 = 0x5678;
+	// on top of the stack
+	const RETURN: u16 = 0x1234;
+
+	// CPU and memory around a single instruction
+	struct State {
+		cpu: CPU,
+		alu: ALU,
+		mem: Vec<u8>
+	}
+
+	impl State {
+		fn new() -> State {
+			let mut s = State {cpu: CPU::new(), alu: ALU::new(), mem: Vec::new()};
+			s.reset();
+			s
+		}
+
+		// registers hold distinct values, the ALU holds flags from an earlier operation
+		// that must not leak into the results
+		fn reset(&mut self) {
+			self.cpu = CPU::new();
+			self.cpu.a = 0xAB;
+			self.cpu.set_bc(0x0123);
+			self.cpu.set_de(0x4567);
+			self.cpu.set_hl(HL);
+			self.cpu.pc = PC;
+			self.cpu.sp = SP;
+			self.alu.cy = true;
+			self.alu.ac = true;
+			self.mem = vec![0; 0x10000];
+			self.mem[HL as usize] = 0xCD;
+			self.mem[SP as usize] = RETURN as u8;
+			self.mem[SP as usize + 1] = (RETURN >> 8) as u8;
+		}
+
+		fn run(&mut self, opcode: u8, arg1: u8, arg2: u8) -> u8 {
+			execute(&mut self.cpu, &mut self.alu, &mut self.mem, opcode, arg1, arg2)
+		}
+
+		// B C D E H L M A, like the opcodes number them
+		fn reg(&self, r: u8) -> u8 {
+			match r {
+				0 => self.cpu.b,
+				1 => self.cpu.c,
+				2 => self.cpu.d,
+				3 => self.cpu.e,
+				4 => self.cpu.h,
+				5 => self.cpu.l,
+				6 => self.mem[self.cpu.get_hl() as usize],
+				_ => self.cpu.a
+			}
+		}
+
+		fn set_reg(&mut self, r: u8, val: u8) {
+			match r {
+				0 => self.cpu.b = val,
+				1 => self.cpu.c = val,
+				2 => self.cpu.d = val,
+				3 => self.cpu.e = val,
+				4 => self.cpu.h = val,
+				5 => self.cpu.l = val,
+				6 => self.mem[self.cpu.get_hl() as usize] = val,
+				_ => self.cpu.a = val
+			}
+		}
+
+		// BC DE HL SP
+		fn pair(&self, rp: u8) -> u16 {
+			match rp {
+				0 => self.cpu.get_bc(),
+				1 => self.cpu.get_de(),
+				2 => self.cpu.get_hl(),
+				_ => self.cpu.sp
+			}
+		}
+
+		fn set_pair(&mut self, rp: u8, val: u16) {
+			match rp {
+				0 => self.cpu.set_bc(val),
+				1 => self.cpu.set_de(val),
+				2 => self.cpu.set_hl(val),
+				_ => self.cpu.sp = val
+			}
+		}
+
+		fn psw(&self) -> u8 {
+			self.cpu.cc.get_psw()
+		}
+
+		fn word(&self, addr: u16) -> u16 {
+			(self.mem[addr.wrapping_add(1) as usize] as u16) << 8 | self.mem[addr as usize] as u16
+		}
+	}
+
+	// PSW of a result, bit 1 is always set
+	fn flags(res: u8, cy: bool, ac: bool) -> u8 {
+		let mut f = 0x02;
+		if res & 0x80 != 0 { f |= FLAG_S; }
+		if res == 0 { f |= FLAG_Z; }
+		if res.count_ones() % 2 == 0 { f |= FLAG_P; }
+		if ac { f |= FLAG_AC; }
+		if cy { f |= FLAG_CY; }
+		f
+	}
+
+	// whether a conditional jump, call or return is taken: NZ Z NC C PO PE P M
+	fn taken(opcode: u8, psw: u8) -> bool {
+		let (flag, set) = match (opcode >> 3) & 7 {
+			0 => (FLAG_Z, false),
+			1 => (FLAG_Z, true),
+			2 => (FLAG_CY, false),
+			3 => (FLAG_CY, true),
+			4 => (FLAG_P, false),
+			5 => (FLAG_P, true),
+			6 => (FLAG_S, false),
+			_ => (FLAG_S, true)
+		};
+		(psw & flag != 0) == set
+	}
+
+	#[test]
+	fn cycles_of_every_opcode() {
+		let mut s = State::new();
+		for opcode in 0..=255u8 {
+			s.reset();
+			let expected = if opcode == 0x76 { 0 } else { CYCLES8080[opcode as usize] };
+			assert_eq!(s.run(opcode, ARG1, ARG2), expected, "opcode {:02X}", opcode);
+		}
+
+		// a few from the datasheet
+		let known = [(0x00, 4), (0x01, 10), (0x22, 16), (0x2A, 16), (0x32, 13), (0x34, 10), (0x36, 10),
+			(0x41, 5), (0x46, 7), (0x70, 7), (0x80, 4), (0x86, 7), (0x09, 10), (0xC3, 10), (0xC5, 11),
+			(0xC9, 10), (0xCD, 17), (0xE3, 18), (0xEB, 5), (0xF9, 5), (0xFE, 7), (0xFF, 11)];
+		for &(opcode, cycles) in known.iter() {
+			assert_eq!(CYCLES8080[opcode as usize], cycles, "opcode {:02X}", opcode);
+		}
+	}
+
+	#[test]
+	fn pc_and_sp_of_every_opcode() {
+		let mut s = State::new();
+		for &psw in [0x00, 0xFF].iter() {
+			for opcode in 0..=255u8 {
+				s.reset();
+				s.cpu.cc.set_psw(psw);
+				s.run(opcode, ARG1, ARG2);
+
+				let next = PC + instr_len(opcode) as u16;
+				let (pc, sp) = match opcode {
+					0x31 => (next, ARGS),
+					0x33 => (next, SP + 1),
+					0x3B => (next, SP - 1),
+					0x76 => (PC + 1, SP),
+					0xC1 | 0xD1 | 0xE1 | 0xF1 => (next, SP + 2),
+					0xC5 | 0xD5 | 0xE5 | 0xF5 => (next, SP - 2),
+					0xC3 | 0xCB => (ARGS, SP),
+					0xC9 | 0xD9 => (RETURN, SP + 2),
+					0xCD | 0xDD | 0xED | 0xFD => (ARGS, SP - 2),
+					0xE9 => (HL, SP),
+					0xF9 => (next, HL),
+					_ if opcode & 0xC7 == 0xC0 => if taken(opcode, psw) { (RETURN, SP + 2) } else { (next, SP) },
+					_ if opcode & 0xC7 == 0xC2 => if taken(opcode, psw) { (ARGS, SP) } else { (next, SP) },
+					_ if opcode & 0xC7 == 0xC4 => if taken(opcode, psw) { (ARGS, SP - 2) } else { (next, SP) },
+					_ if opcode & 0xC7 == 0xC7 => ((opcode & 0x38) as u16, SP - 2),
+					_ => (next, SP)
+				};
+				assert_eq!((s.cpu.pc, s.cpu.sp), (pc, sp), "opcode {:02X} with PSW {:02X}", opcode, psw);
+
+				// calls push the address of the next instruction
+				if sp == SP - 2 && opcode & 0xCF != 0xC5 {
+					assert_eq!(s.word(sp), next, "opcode {:02X} with PSW {:02X}", opcode, psw);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn flags_are_kept() {
+		let changes_flags = |opcode: u8| match opcode {
+			0x07 | 0x0F | 0x17 | 0x1F | 0x27 | 0x37 | 0x3F | 0xF1 => true,
+			0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => true,
+			0x80..=0xBF => true,
+			_ if opcode < 0x40 && opcode & 0x06 == 0x04 => true,	// INR, DCR
+			_ if opcode < 0x40 && opcode & 0x0F == 0x09 => true,	// DAD
+			_ => false
+		};
+
+		let mut s = State::new();
+		for &psw in [0x00, 0xFF].iter() {
+			for opcode in (0..=255u8).filter(|&op| !changes_flags(op)) {
+				s.reset();
+				s.cpu.cc.set_psw(psw);
+				let before = s.psw();
+				s.run(opcode, ARG1, ARG2);
+				assert_eq!(s.psw(), before, "opcode {:02X}", opcode);
+			}
+		}
+	}
+
+	#[test]
+	fn mov() {
+		let mut s = State::new();
+		for dst in 0..8 {
+			for src in 0..8 {
+				let opcode = 0x40 | dst << 3 | src;
+				if opcode == 0x76 {
+					continue;
+				}
+				s.reset();
+				let regs: Vec<u8> = (0..8).map(|r| s.reg(r)).collect();
+				assert_eq!(s.run(opcode, 0, 0), if dst == 6 || src == 6 { 7 } else { 5 });
+				for r in 0..8 {
+					let expected = if r == dst { regs[src as usize] } else { regs[r as usize] };
+					// M where HL pointed before, MOV H and MOV L move it
+					let actual = if r == 6 { s.mem[HL as usize] } else { s.reg(r) };
+					assert_eq!(actual, expected, "MOV {},{} register {}", dst, src, r);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn mvi() {
+		let mut s = State::new();
+		for r in 0..8 {
+			s.reset();
+			s.run(0x06 | r << 3, 0x5A, 0);
+			assert_eq!(s.reg(r), 0x5A, "register {}", r);
+		}
+	}
+
+	#[test]
+	fn register_pairs() {
+		let mut s = State::new();
+		for rp in 0..4 {
+			s.reset();
+			s.run(0x01 | rp << 4, ARG1, ARG2);
+			assert_eq!(s.pair(rp), ARGS, "LXI {}", rp);
+
+			for &(val, inx, dcx) in [(0x1234, 0x1235, 0x1233), (0xFFFF, 0x0000, 0xFFFE), (0x0000, 0x0001, 0xFFFF)].iter() {
+				s.reset();
+				s.set_pair(rp, val);
+				s.run(0x03 | rp << 4, 0, 0);
+				assert_eq!(s.pair(rp), inx, "INX {} of {:04X}", rp, val);
+
+				s.reset();
+				s.set_pair(rp, val);
+				s.run(0x0B | rp << 4, 0, 0);
+				assert_eq!(s.pair(rp), dcx, "DCX {} of {:04X}", rp, val);
+			}
+		}
+	}
+
+	#[test]
+	fn dad() {
+		let mut s = State::new();
+		for rp in 0..4 {
+			for &(hl, val) in [(0x1234, 0x4321), (0xFFFF, 0x0001), (0x8000, 0x8000), (0x0000, 0x0000)].iter() {
+				for &psw in [0x00, 0xFF].iter() {
+					s.reset();
+					s.cpu.cc.set_psw(psw);
+					s.set_pair(rp, val);
+					s.cpu.set_hl(hl);
+					let (hl, val) = (s.cpu.get_hl(), s.pair(rp));
+					let before = s.psw();
+					s.run(0x09 | rp << 4, 0, 0);
+
+					let (sum, cy) = hl.overflowing_add(val);
+					assert_eq!(s.cpu.get_hl(), sum, "DAD {} of {:04X}", rp, val);
+					assert_eq!(s.psw(), before & !FLAG_CY | cy as u8, "DAD {} of {:04X}", rp, val);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn loads_and_stores() {
+		let mut s = State::new();
+
+		s.run(0x02, 0, 0);
+		assert_eq!(s.mem[0x0123], 0xAB, "STAX B");
+		s.reset();
+		s.run(0x12, 0, 0);
+		assert_eq!(s.mem[0x4567], 0xAB, "STAX D");
+
+		s.reset();
+		s.mem[0x0123] = 0x11;
+		s.run(0x0A, 0, 0);
+		assert_eq!(s.cpu.a, 0x11, "LDAX B");
+		s.reset();
+		s.mem[0x4567] = 0x22;
+		s.run(0x1A, 0, 0);
+		assert_eq!(s.cpu.a, 0x22, "LDAX D");
+
+		s.reset();
+		s.run(0x32, ARG1, ARG2);
+		assert_eq!(s.mem[ARGS as usize], 0xAB, "STA");
+		s.reset();
+		s.mem[ARGS as usize] = 0x33;
+		s.run(0x3A, ARG1, ARG2);
+		assert_eq!(s.cpu.a, 0x33, "LDA");
+
+		s.reset();
+		s.run(0x22, ARG1, ARG2);
+		assert_eq!(s.word(ARGS), HL, "SHLD");
+		s.reset();
+		s.mem[ARGS as usize] = 0x44;
+		s.mem[ARGS as usize + 1] = 0x55;
+		s.run(0x2A, ARG1, ARG2);
+		assert_eq!(s.cpu.get_hl(), 0x5544, "LHLD");
+
+		// the second byte wraps around to the start of memory
+		s.reset();
+		s.run(0x22, 0xFF, 0xFF);
+		assert_eq!((s.mem[0xFFFF], s.mem[0x0000]), (0x89, 0x30), "SHLD at FFFF");
+		s.reset();
+		s.mem[0xFFFF] = 0x66;
+		s.mem[0x0000] = 0x77;
+		s.run(0x2A, 0xFF, 0xFF);
+		assert_eq!(s.cpu.get_hl(), 0x7766, "LHLD at FFFF");
+	}
+
+	#[test]
+	fn push_and_pop() {
+		let mut s = State::new();
+		for rp in 0..3 {
+			s.reset();
+			let val = s.pair(rp);
+			s.run(0xC5 | rp << 4, 0, 0);
+			assert_eq!(s.word(SP - 2), val, "PUSH {}", rp);
+
+			s.reset();
+			s.run(0xC1 | rp << 4, 0, 0);
+			assert_eq!(s.pair(rp), RETURN, "POP {}", rp);
+		}
+
+		s.reset();
+		s.cpu.cc.set_psw(0xFF);
+		s.run(0xF5, 0, 0);
+		assert_eq!(s.word(SP - 2), 0xABD7, "PUSH PSW");
+
+		// the unused bits read as 0, except bit 1 that is always set
+		s.reset();
+		s.mem[SP as usize] = 0xFF;
+		s.run(0xF1, 0, 0);
+		assert_eq!((s.cpu.a, s.psw()), (0x12, 0xD7), "POP PSW");
+		s.reset();
+		s.mem[SP as usize] = 0x00;
+		s.run(0xF1, 0, 0);
+		assert_eq!(s.psw(), 0x02, "POP PSW");
+
+		// the stack pointer wraps around
+		s.reset();
+		s.cpu.sp = 0x0001;
+		s.run(0xC5, 0, 0);
+		assert_eq!((s.cpu.sp, s.mem[0x0000], s.mem[0xFFFF]), (0xFFFF, 0x01, 0x23), "PUSH at 0001");
+		s.reset();
+		s.cpu.sp = 0xFFFF;
+		s.mem[0xFFFF] = 0x88;
+		s.mem[0x0000] = 0x99;
+		s.run(0xC1, 0, 0);
+		assert_eq!((s.cpu.sp, s.cpu.get_bc()), (0x0001, 0x9988), "POP at FFFF");
+	}
+
+	#[test]
+	fn exchanges() {
+		let mut s = State::new();
+		s.run(0xEB, 0, 0);
+		assert_eq!((s.cpu.get_de(), s.cpu.get_hl()), (HL, 0x4567), "XCHG");
+
+		s.reset();
+		s.run(0xE3, 0, 0);
+		assert_eq!((s.cpu.get_hl(), s.word(SP), s.cpu.sp), (RETURN, HL, SP), "XTHL");
+
+		s.reset();
+		s.run(0xF9, 0, 0);
+		assert_eq!(s.cpu.sp, HL, "SPHL");
+	}
+
+	// expected A and PSW of ADD ADC SUB SBB ANA XRA ORA CMP
+	fn accumulator(op: u8, x: u8, y: u8, carry: u8) -> (u8, u8) {
+		let c = if op & 1 == 1 && op < 4 { carry } else { 0 };
+		match op {
+			0 | 1 => {
+				let res = x as u16 + y as u16 + c as u16;
+				(res as u8, flags(res as u8, res > 0xFF, (x & 0x0F) + (y & 0x0F) + c > 0x0F))
+			},
+			2 | 3 | 7 => {
+				let res = x.wrapping_sub(y).wrapping_sub(c);
+				let borrow = (y as u16 + c as u16) > x as u16;
+				// carry out of bit 3 when adding the complement
+				let ac = (x & 0x0F) + (!y & 0x0F) + (1 - c) > 0x0F;
+				(if op == 7 { x } else { res }, flags(res, borrow, ac))
+			},
+			4 => (x & y, flags(x & y, false, false)),
+			5 => (x ^ y, flags(x ^ y, false, false)),
+			_ => (x | y, flags(x | y, false, false))
+		}
+	}
+
+	const SAMPLES: [u8; 8] = [0x00, 0x01, 0x0F, 0x10, 0x7F, 0x80, 0x9A, 0xFF];
+
+	#[test]
+	fn accumulator_registers() {
+		let mut s = State::new();
+		for op in 0..8 {
+			for r in 0..8 {
+				let opcode = 0x80 | op << 3 | r;
+				for &x in SAMPLES.iter() {
+					for &y in SAMPLES.iter() {
+						for carry in 0..2 {
+							s.reset();
+							s.cpu.cc.set_cy(carry);
+							s.cpu.a = x;
+							s.set_reg(r, y);
+							let y = s.reg(r);
+							let x = s.cpu.a;
+							s.run(opcode, 0, 0);
+							assert_eq!((s.cpu.a, s.psw()), accumulator(op, x, y, carry),
+								"opcode {:02X} with {:02X}, {:02X}, carry {}", opcode, x, y, carry);
+						}
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn accumulator_immediate() {
+		let mut s = State::new();
+		for op in 0..8 {
+			let opcode = 0xC6 | op << 3;
+			for &x in SAMPLES.iter() {
+				for &y in SAMPLES.iter() {
+					for carry in 0..2 {
+						s.reset();
+						s.cpu.cc.set_cy(carry);
+						s.cpu.a = x;
+						s.run(opcode, y, 0);
+						assert_eq!((s.cpu.a, s.psw()), accumulator(op, x, y, carry),
+							"opcode {:02X} with {:02X}, {:02X}, carry {}", opcode, x, y, carry);
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn increment_and_decrement() {
+		let mut s = State::new();
+		for r in 0..8 {
+			for &x in SAMPLES.iter() {
+				for carry in 0..2 {
+					s.reset();
+					s.cpu.cc.set_cy(carry);
+					s.set_reg(r, x);
+					s.run(0x04 | r << 3, 0, 0);
+					let res = x.wrapping_add(1);
+					assert_eq!((s.reg(r), s.psw()), (res, flags(res, carry == 1, x & 0x0F == 0x0F)), "INR {} of {:02X}", r, x);
+
+					s.reset();
+					s.cpu.cc.set_cy(carry);
+					s.set_reg(r, x);
+					s.run(0x05 | r << 3, 0, 0);
+					let res = x.wrapping_sub(1);
+					assert_eq!((s.reg(r), s.psw()), (res, flags(res, carry == 1, x & 0x0F != 0)), "DCR {} of {:02X}", r, x);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn daa() {
+		let mut s = State::new();
+		// A and PSW before and after
+		let cases = [
+			(0x15, 0x02, 0x15, flags(0x15, false, false)),
+			(0x0A, 0x02, 0x10, flags(0x10, false, true)),
+			(0x9B, 0x02, 0x01, flags(0x01, true, true)),
+			(0xA0, 0x02, 0x00, flags(0x00, true, false)),
+			(0x02, 0x02 | FLAG_AC, 0x08, flags(0x08, false, false)),
+			(0x02, 0x02 | FLAG_CY, 0x62, flags(0x62, true, false)),
+			(0x9A, 0x02, 0x00, flags(0x00, true, true))
+		];
+		for &(a, psw, res, expected) in cases.iter() {
+			s.reset();
+			s.cpu.a = a;
+			s.cpu.cc.set_psw(psw);
+			s.run(0x27, 0, 0);
+			assert_eq!((s.cpu.a, s.psw()), (res, expected), "DAA of {:02X} with PSW {:02X}", a, psw);
+		}
+	}
+
+	#[test]
+	fn rotates() {
+		let mut s = State::new();
+		// opcode, A and carry before and after
+		let cases = [
+			(0x07, 0x81, 0, 0x03, 1), (0x07, 0x40, 1, 0x80, 0),
+			(0x0F, 0x81, 0, 0xC0, 1), (0x0F, 0x02, 1, 0x01, 0),
+			(0x17, 0x80, 0, 0x00, 1), (0x17, 0x01, 1, 0x03, 0),
+			(0x1F, 0x01, 0, 0x00, 1), (0x1F, 0x80, 1, 0xC0, 0)
+		];
+		for &(opcode, a, carry, res, cy) in cases.iter() {
+			for &psw in [0x00, 0xFE].iter() {
+				s.reset();
+				s.cpu.cc.set_psw(psw | carry);
+				s.cpu.a = a;
+				let before = s.psw();
+				s.run(opcode, 0, 0);
+				assert_eq!((s.cpu.a, s.psw()), (res, before & !FLAG_CY | cy), "opcode {:02X} of {:02X}", opcode, a);
+			}
+		}
+	}
+
+	#[test]
+	fn carry_and_complement() {
+		let mut s = State::new();
+		for carry in 0..2 {
+			s.reset();
+			s.cpu.cc.set_cy(carry);
+			s.run(0x37, 0, 0);
+			assert_eq!(s.cpu.cc.cy(), 1, "STC");
+
+			s.reset();
+			s.cpu.cc.set_cy(carry);
+			s.run(0x3F, 0, 0);
+			assert_eq!(s.cpu.cc.cy(), carry ^ 1, "CMC");
+		}
+
+		s.reset();
+		s.run(0x2F, 0, 0);
+		assert_eq!(s.cpu.a, 0x54, "CMA");
+	}
+
+	#[test]
+	fn interrupt_enable() {
+		let mut s = State::new();
+		s.run(0xFB, 0, 0);
+		assert_eq!(s.cpu.int_enable, 1, "EI");
+		s.run(0xF3, 0, 0);
+		assert_eq!(s.cpu.int_enable, 0, "DI");
+	}
+
+	// I/O is done by the machine, the CPU only skips the port
+	#[test]
+	fn io() {
+		let mut s = State::new();
+		for &opcode in [0xD3, 0xDB].iter() {
+			s.reset();
+			let regs = s.cpu.registers();
+			s.run(opcode, ARG1, 0);
+			assert_eq!(s.cpu.registers(), [regs[0], regs[1], regs[2], regs[3], PC + 2, SP], "opcode {:02X}", opcode);
+		}
+	}
+
+	#[test]
+	fn undocumented_aliases() {
+		let mut s = State::new();
+		let mut t = State::new();
+		let aliases = [(0x08, 0x00), (0x10, 0x00), (0x18, 0x00), (0x20, 0x00), (0x28, 0x00), (0x30, 0x00), (0x38, 0x00),
+			(0xCB, 0xC3), (0xD9, 0xC9), (0xDD, 0xCD), (0xED, 0xCD), (0xFD, 0xCD)];
+		for &(alias, opcode) in aliases.iter() {
+			s.reset();
+			t.reset();
+			let cycles = (s.run(alias, ARG1, ARG2), t.run(opcode, ARG1, ARG2));
+			assert_eq!(cycles.0, cycles.1, "cycles of {:02X}", alias);
+			assert_eq!(s.cpu.registers(), t.cpu.registers(), "registers after {:02X}", alias);
+			assert!(s.mem == t.mem, "memory after {:02X}", alias);
+		}
+	}
+}
@@ -0,0 +1,37 @@
+use display::*;
+use artwork::ArtworkConfig;
+
+// Built-in settings for the different cabinets the game was sold in.
+#[derive(Debug, Clone)]
+pub struct Profile {
+	pub name: String,
+	pub display: DisplayConfig,
+	pub artwork: ArtworkConfig,
+	// output port and bit that flips the screen for player 2 on cocktail tables
+	pub flip: Option<(u8, u8)>
+}
+
+impl Profile {
+	pub fn builtin(name: &str) -> Option<Profile> {
+		let overlay = match name {
+			// upright cabinet with the coloured cellophane overlay
+			"invaders" => "invaders",
+			// black and white monitor without overlay
+			"invaders-bw" => "mono",
+			_ => return None
+		};
+
+		Some(Profile {
+			name: name.to_string(),
+			display: DisplayConfig::new(Overlay::builtin(overlay).unwrap()),
+			artwork: ArtworkConfig::for_profile(name),
+			flip: Some((5, 5))
+		})
+	}
+}
+
+impl Default for Profile {
+	fn default() -> Profile {
+		Profile::builtin("invaders").unwrap()
+	}
+}
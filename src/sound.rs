@@ -0,0 +1,190 @@
+use std::io::*;
+use std::fs::File;
+use std::cmp;
+
+use synth;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoundEvent {
+	pub cycle: u64,
+	pub sound: usize,
+	pub on: bool
+}
+
+// Port 3 bit 5 switches the amplifier on
+const AMP_ENABLE: u8 = 0x20;
+
+// Only the UFO sound loops while its bit is set, all others are one-shots.
+pub fn is_looping(sound: usize) -> bool {
+	sound == 0
+}
+
+// Decodes the sound bits of port 3 (0-3) and port 5 (0-4) into start/stop changes.
+// A rising edge (re)starts a sound. A falling edge only stops the looping UFO sound,
+// one-shots play until they are done. Switching the amplifier off stops everything.
+#[derive(Debug)]
+pub struct SoundPorts {
+	port3: u8,
+	port5: u8
+}
+
+impl SoundPorts {
+	pub fn new() -> SoundPorts {
+		SoundPorts {
+			port3: 0,
+			port5: 0
+		}
+	}
+
+	pub fn enabled(&self) -> bool {
+		self.port3 & AMP_ENABLE != 0
+	}
+
+	// returns (sound, on) pairs, on meaning start from the beginning and off stop now
+	pub fn write(&mut self, port: u8, value: u8) -> Vec<(usize, bool)> {
+		let mut changes = Vec::new();
+		let was_enabled = self.enabled();
+
+		let (old, first, count) = match port {
+			3 => (self.port3, 0, 4),
+			5 => (self.port5, 4, 5),
+			_ => return changes
+		};
+		if port == 3 {
+			self.port3 = value;
+		}
+		else {
+			self.port5 = value;
+		}
+
+		if was_enabled && !self.enabled() {
+			for sound in 0..synth::SOUND_COUNT {
+				changes.push((sound, false));
+			}
+			return changes;
+		}
+		if !self.enabled() {
+			return changes;
+		}
+		if !was_enabled && self.port3 & 1 == 1 {
+			// UFO bit was already set while muted
+			changes.push((0, true));
+		}
+
+		for bit in 0..count {
+			let sound = first + bit as usize;
+			let before = (old >> bit) & 1 == 1;
+			let now = (value >> bit) & 1 == 1;
+			// the UFO may have been started above already
+			if !before && now && !changes.contains(&(sound, true)) {
+				changes.push((sound, true));
+			}
+			else if before && !now && is_looping(sound) {
+				changes.push((sound, false));
+			}
+		}
+		changes
+	}
+}
+
+// Records the decoded sound changes with the cycle they happened at.
+#[derive(Debug)]
+pub struct SoundTimeline {
+	events: Vec<SoundEvent>
+}
+
+impl SoundTimeline {
+	pub fn new() -> SoundTimeline {
+		SoundTimeline {
+			events: Vec::new()
+		}
+	}
+
+	pub fn push(&mut self, event: SoundEvent) {
+		self.events.push(event);
+	}
+
+	pub fn events(&self) -> &[SoundEvent] {
+		&self.events
+	}
+
+	pub fn drain(&mut self) -> Vec<SoundEvent> {
+		self.events.drain(..).collect()
+	}
+}
+
+pub fn write_log(filename: &str, events: &[SoundEvent]) -> Result<()> {
+	let mut file = File::create(filename)?;
+	for e in events {
+		writeln!(file, "{} {} {}", e.cycle, e.sound, if e.on {"on"} else {"off"})?;
+	}
+	Ok(())
+}
+
+// Mixes the synthesized sounds at the times given by the events up to end_cycle.
+pub fn render(events: &[SoundEvent], end_cycle: u64, cpu_hz: u64) -> Vec<i16> {
+	let sounds: Vec<Vec<i16>> = (0..synth::SOUND_COUNT).map(synth::sound).collect();
+	let to_sample = |cycle: u64| (cycle * synth::SAMPLE_RATE as u64 / cpu_hz) as usize;
+	let end = to_sample(end_cycle);
+
+	let mut mix: Vec<i32> = Vec::new();
+	mix.resize(end, 0);
+	for (n, e) in events.iter().enumerate() {
+		if !e.on {
+			continue;
+		}
+		let sample = &sounds[e.sound];
+		let start = to_sample(e.cycle);
+
+		// plays until the next change of the same sound
+		let next = events[n + 1..].iter().find(|o| o.sound == e.sound);
+		let mut stop = match next {
+			Some(next) => to_sample(next.cycle),
+			None => end
+		};
+		if !is_looping(e.sound) {
+			stop = cmp::min(stop, start + sample.len());
+		}
+
+		for i in start..cmp::min(stop, end) {
+			mix[i] += sample[(i - start) % sample.len()] as i32;
+		}
+	}
+
+	mix.iter().map(|&s| cmp::max(cmp::min(s, i16::max_value() as i32), i16::min_value() as i32) as i16).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn edges() {
+		let mut ports = SoundPorts::new();
+		assert_eq!(ports.write(3, AMP_ENABLE | 0x02), vec![(1, true)]);
+		// held bits don't restart
+		assert_eq!(ports.write(3, AMP_ENABLE | 0x02), vec![]);
+		// one-shots play on, the UFO stops
+		assert_eq!(ports.write(3, AMP_ENABLE | 0x01), vec![(0, true)]);
+		assert_eq!(ports.write(3, AMP_ENABLE), vec![(0, false)]);
+		assert_eq!(ports.write(5, 0x11), vec![(4, true), (8, true)]);
+	}
+
+	#[test]
+	fn amplifier() {
+		let mut ports = SoundPorts::new();
+		// muted
+		assert_eq!(ports.write(3, 0x01), vec![]);
+		assert_eq!(ports.write(3, AMP_ENABLE | 0x01), vec![(0, true)]);
+		let all: Vec<(usize, bool)> = (0..synth::SOUND_COUNT).map(|sound| (sound, false)).collect();
+		assert_eq!(ports.write(3, 0x01), all);
+	}
+
+	#[test]
+	fn enable_with_ufo_bit() {
+		let mut ports = SoundPorts::new();
+		// the UFO bit rises with the amplifier in the same write, it starts once
+		assert_eq!(ports.write(3, AMP_ENABLE | 0x03), vec![(0, true), (1, true)]);
+	}
+}
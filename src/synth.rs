@@ -0,0 +1,220 @@
+use std::io::*;
+use std::fs::File;
+use std::f32::consts::PI;
+
+pub const SAMPLE_RATE: u32 = 22050;
+pub const SOUND_COUNT: usize = 9;
+
+const VOLUME: f32 = 0.5;
+
+// Approximations of the discrete sound circuits on the Space Invaders board.
+// The index matches the sample files: sound/0.wav ... sound/8.wav
+//   0: UFO (looping)    1: shot          2: player death    3: invader death
+//   4-7: fleet steps    8: UFO hit
+pub fn sound(index: usize) -> Vec<i16> {
+	match index {
+		0 => ufo(),
+		1 => shot(),
+		2 => player_death(),
+		3 => invader_death(),
+		4 => fleet(62.0),
+		5 => fleet(55.0),
+		6 => fleet(49.0),
+		7 => fleet(44.0),
+		8 => ufo_hit(),
+		_ => Vec::new()
+	}
+}
+
+pub fn render_all(dir: &str) -> Result<()> {
+	for i in 0..SOUND_COUNT {
+		write_wav(&format!("{}/{}.wav", dir, i), &sound(i))?;
+	}
+	Ok(())
+}
+
+pub fn write_wav(filename: &str, samples: &[i16]) -> Result<()> {
+	let data_len = samples.len() as u32 * 2;
+	let mut buffer: Vec<u8> = Vec::with_capacity(44 + data_len as usize);
+
+	buffer.extend_from_slice(b"RIFF");
+	push_u32(&mut buffer, 36 + data_len);
+	buffer.extend_from_slice(b"WAVE");
+
+	buffer.extend_from_slice(b"fmt ");
+	push_u32(&mut buffer, 16);
+	push_u16(&mut buffer, 1); // PCM
+	push_u16(&mut buffer, 1); // mono
+	push_u32(&mut buffer, SAMPLE_RATE);
+	push_u32(&mut buffer, SAMPLE_RATE * 2);
+	push_u16(&mut buffer, 2);
+	push_u16(&mut buffer, 16);
+
+	buffer.extend_from_slice(b"data");
+	push_u32(&mut buffer, data_len);
+	for s in samples {
+		push_u16(&mut buffer, *s as u16);
+	}
+
+	let mut file = File::create(filename)?;
+	file.write_all(&buffer)
+}
+
+// Reads 8 or 16 bit PCM files, stereo is mixed down to mono.
+// Returns the samples and their sample rate.
+pub fn read_wav(filename: &str) -> Result<(Vec<i16>, u32)> {
+	let mut buffer = vec![];
+	File::open(filename)?.read_to_end(&mut buffer)?;
+
+	let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, format!("{}: {}", filename, msg));
+	if buffer.len() < 12 || &buffer[0..4] != b"RIFF" || &buffer[8..12] != b"WAVE" {
+		return Err(invalid("not a WAV file"));
+	}
+
+	let mut format: Option<(u16, u16, u32, u16)> = None;
+	let mut pos = 12;
+	while pos + 8 <= buffer.len() {
+		let id = &buffer[pos..pos + 4];
+		let len = read_u32(&buffer[pos + 4..]) as usize;
+		let body = &buffer[pos + 8..buffer.len().min(pos + 8 + len)];
+
+		if id == b"fmt " && body.len() >= 16 {
+			format = Some((read_u16(body), read_u16(&body[2..]), read_u32(&body[4..]), read_u16(&body[14..])));
+		}
+		else if id == b"data" {
+			let (tag, channels, rate, bits) = format.ok_or_else(|| invalid("data before format"))?;
+			if tag != 1 || channels == 0 || (bits != 8 && bits != 16) {
+				return Err(invalid("unsupported format"));
+			}
+
+			let width = bits as usize / 8;
+			let frame = width * channels as usize;
+			let samples = body.chunks(frame).filter(|f| f.len() == frame).map(|f| {
+				let sum: i32 = f.chunks(width).map(|s| {
+					if width == 1 { (s[0] as i32 - 128) << 8 } else { read_u16(s) as i16 as i32 }
+				}).sum();
+				(sum / channels as i32) as i16
+			}).collect();
+			return Ok((samples, rate));
+		}
+		pos += 8 + len + (len & 1);
+	}
+	Err(invalid("no data"))
+}
+
+fn read_u16(buffer: &[u8]) -> u16 {
+	buffer[0] as u16 | (buffer[1] as u16) << 8
+}
+
+fn read_u32(buffer: &[u8]) -> u32 {
+	read_u16(buffer) as u32 | (read_u16(&buffer[2..]) as u32) << 16
+}
+
+fn push_u16(buffer: &mut Vec<u8>, val: u16) {
+	buffer.push(val as u8);
+	buffer.push((val >> 8) as u8);
+}
+
+fn push_u32(buffer: &mut Vec<u8>, val: u32) {
+	push_u16(buffer, val as u16);
+	push_u16(buffer, (val >> 16) as u16);
+}
+
+
+// 17 bit shift register like the noise source on the board
+struct Noise {
+	state: u32
+}
+
+impl Noise {
+	fn new() -> Noise {
+		Noise {
+			state: 0x1FFFF
+		}
+	}
+
+	fn next(&mut self) -> f32 {
+		let bit = ((self.state >> 16) ^ (self.state >> 13)) & 1;
+		self.state = ((self.state << 1) | bit) & 0x1FFFF;
+		if bit == 1 { 1.0 } else { -1.0 }
+	}
+}
+
+fn square(phase: f32) -> f32 {
+	if phase.fract() < 0.5 { 1.0 } else { -1.0 }
+}
+
+fn render<F: FnMut(f32) -> f32>(secs: f32, mut f: F) -> Vec<i16> {
+	let n = (secs * SAMPLE_RATE as f32) as usize;
+	(0..n).map(|i| {
+		let v = f(i as f32 / SAMPLE_RATE as f32) * VOLUME;
+		(v.max(-1.0).min(1.0) * i16::max_value() as f32) as i16
+	}).collect()
+}
+
+fn ufo() -> Vec<i16> {
+	// one period of the siren so the sample loops seamlessly
+	let lfo_hz = 6.0;
+	let mut phase = 0.0;
+	render(1.0 / lfo_hz, |t| {
+		let lfo = 0.5 - 0.5 * (2.0 * PI * lfo_hz * t).cos();
+		let freq = 500.0 + 400.0 * lfo;
+		phase += freq / SAMPLE_RATE as f32;
+		(2.0 * PI * phase).sin() * 0.8
+	})
+}
+
+fn shot() -> Vec<i16> {
+	let mut noise = Noise::new();
+	let mut phase = 0.0;
+	render(0.45, |t| {
+		let freq = 1200.0 - 1800.0 * t;
+		phase += freq / SAMPLE_RATE as f32;
+		let env = (-t * 6.0).exp();
+		(square(phase) * 0.6 + noise.next() * 0.2) * env
+	})
+}
+
+fn player_death() -> Vec<i16> {
+	let mut noise = Noise::new();
+	let mut low = 0.0;
+	render(1.2, |t| {
+		// one pole low pass gives the rumble
+		low += (noise.next() - low) * 0.08;
+		let env = (-t * 2.5).exp();
+		low * 2.5 * env
+	})
+}
+
+fn invader_death() -> Vec<i16> {
+	let mut noise = Noise::new();
+	let mut phase = 0.0;
+	render(0.3, |t| {
+		let freq = 600.0 - 1200.0 * t;
+		phase += freq / SAMPLE_RATE as f32;
+		let env = (-t * 12.0).exp();
+		(noise.next() * 0.5 + square(phase) * 0.4) * env
+	})
+}
+
+fn fleet(freq: f32) -> Vec<i16> {
+	let mut phase = 0.0;
+	let mut low = 0.0;
+	render(0.12, |t| {
+		phase += freq / SAMPLE_RATE as f32;
+		low += (square(phase) - low) * 0.1;
+		let env = (-t * 20.0).exp();
+		low * 1.5 * env
+	})
+}
+
+fn ufo_hit() -> Vec<i16> {
+	let mut phase = 0.0;
+	render(1.0, |t| {
+		let lfo = square(t * 10.0);
+		let freq = 280.0 + 120.0 * lfo;
+		phase += freq / SAMPLE_RATE as f32;
+		let env = (-t * 1.5).exp();
+		square(phase) * 0.7 * env
+	})
+}
@@ -0,0 +1,358 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::*;
+use std::thread;
+use toml;
+
+use cpu::CPU;
+use disassemble::disassemble;
+
+const MAGIC: &[u8; 8] = b"8080TRC1";
+const RECORD_SIZE: usize = 26;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+	// one line per entry: cycle, disassembly and the registers after it like print_state
+	Text,
+	// MAGIC, then 26 byte records, see `TraceEntry::write_binary`
+	Binary
+}
+
+// instruction groups as in the 8080 manual
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpClass {
+	Transfer,
+	Arithmetic,
+	Logical,
+	Branch,
+	Stack,
+	Io,
+	Control
+}
+
+impl OpClass {
+	pub fn of(opcode: u8) -> OpClass {
+		match opcode {
+			0xD3 | 0xDB => OpClass::Io,
+			0x76 | 0xF3 | 0xFB => OpClass::Control,
+			0x40..=0x7F => OpClass::Transfer,
+			0x80..=0x9F => OpClass::Arithmetic,
+			0xA0..=0xBF => OpClass::Logical,
+			0x00..=0x3F => match opcode & 0x7 {
+				0 => OpClass::Control, // NOP
+				1 if opcode & 0x8 == 0 => OpClass::Transfer, // LXI
+				1 => OpClass::Arithmetic, // DAD
+				2 => OpClass::Transfer, // STAX, LDAX, SHLD, LHLD, STA, LDA
+				3 | 4 | 5 => OpClass::Arithmetic, // INX, DCX, INR, DCR
+				6 => OpClass::Transfer, // MVI
+				_ => if opcode == 0x27 { OpClass::Arithmetic } else { OpClass::Logical } // DAA, rotates, CMA, STC, CMC
+			},
+			_ => match opcode & 0x7 {
+				0 | 2 | 4 | 7 => OpClass::Branch, // Rcc, Jcc, Ccc, RST
+				1 => match opcode {
+					0xC9 | 0xD9 | 0xE9 => OpClass::Branch, // RET, PCHL
+					_ => OpClass::Stack // POP, SPHL
+				},
+				3 => match opcode {
+					0xE3 => OpClass::Stack, // XTHL
+					0xEB => OpClass::Transfer, // XCHG
+					_ => OpClass::Branch // JMP
+				},
+				5 => if opcode & 0x8 == 0 { OpClass::Stack } else { OpClass::Branch }, // PUSH, CALL
+				_ => if opcode < 0xE0 { OpClass::Arithmetic } else { OpClass::Logical } // ADI..SBI, ANI..CPI
+			}
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<OpClass> {
+		match name {
+			"transfer" => Some(OpClass::Transfer),
+			"arithmetic" => Some(OpClass::Arithmetic),
+			"logical" => Some(OpClass::Logical),
+			"branch" => Some(OpClass::Branch),
+			"stack" => Some(OpClass::Stack),
+			"io" => Some(OpClass::Io),
+			"control" => Some(OpClass::Control),
+			_ => None
+		}
+	}
+
+	pub fn name(&self) -> &str {
+		match *self {
+			OpClass::Transfer => "transfer",
+			OpClass::Arithmetic => "arithmetic",
+			OpClass::Logical => "logical",
+			OpClass::Branch => "branch",
+			OpClass::Stack => "stack",
+			OpClass::Io => "io",
+			OpClass::Control => "control"
+		}
+	}
+}
+
+
+// What gets traced. Empty lists don't restrict anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceConfig {
+	pub file: String,
+	pub format: TraceFormat,
+	// inclusive range of instruction addresses
+	pub pc: Option<(u16, u16)>,
+	pub classes: Vec<OpClass>,
+	// only IN and OUT on these ports
+	pub ports: Vec<u8>,
+	pub instructions: bool,
+	pub interrupts: bool,
+	// keep only the last n entries in memory and write them on a crash or breakpoint
+	pub ring: Option<usize>,
+	pub breakpoints: Vec<u16>
+}
+
+impl TraceConfig {
+	pub fn new() -> TraceConfig {
+		TraceConfig {
+			file: "trace.log".to_string(),
+			format: TraceFormat::Text,
+			pc: None,
+			classes: Vec::new(),
+			ports: Vec::new(),
+			instructions: true,
+			interrupts: true,
+			ring: None,
+			breakpoints: Vec::new()
+		}
+	}
+
+	pub fn from_table(table: &toml::value::Table) -> TraceConfig {
+		let mut config = TraceConfig::new();
+		let numbers = |key: &str| -> Vec<i64> {
+			table.get(key).and_then(|v| v.as_array())
+				.map(|a| a.iter().filter_map(|v| v.as_integer()).collect())
+				.unwrap_or_default()
+		};
+
+		if let Some(file) = table.get("file").and_then(|v| v.as_str()) {
+			config.file = file.to_string();
+		}
+		match table.get("format").and_then(|v| v.as_str()) {
+			Some("text") | None => (),
+			Some("binary") => config.format = TraceFormat::Binary,
+			Some(other) => println!("=> Unknown trace format: {}", other)
+		}
+		let pc = numbers("pc");
+		if pc.len() == 2 {
+			config.pc = Some((pc[0] as u16, pc[1] as u16));
+		}
+		if let Some(classes) = table.get("classes").and_then(|v| v.as_array()) {
+			for name in classes.iter().filter_map(|v| v.as_str()) {
+				match OpClass::from_name(name) {
+					Some(class) => config.classes.push(class),
+					None => println!("=> Unknown instruction class: {}", name)
+				}
+			}
+		}
+		config.ports = numbers("ports").iter().map(|&p| p as u8).collect();
+		if let Some(instructions) = table.get("instructions").and_then(|v| v.as_bool()) {
+			config.instructions = instructions;
+		}
+		if let Some(interrupts) = table.get("interrupts").and_then(|v| v.as_bool()) {
+			config.interrupts = interrupts;
+		}
+		config.ring = table.get("ring").and_then(|v| v.as_integer()).filter(|&n| n > 0).map(|n| n as usize);
+		config.breakpoints = numbers("breakpoints").iter().map(|&pc| pc as u16).collect();
+		config
+	}
+
+	pub fn to_table(&self) -> toml::value::Table {
+		let mut table = toml::value::Table::new();
+		let numbers = |values: Vec<i64>| toml::Value::Array(values.into_iter().map(toml::Value::Integer).collect());
+
+		table.insert("file".to_string(), toml::Value::String(self.file.clone()));
+		table.insert("format".to_string(), toml::Value::String(match self.format {
+			TraceFormat::Text => "text",
+			TraceFormat::Binary => "binary"
+		}.to_string()));
+		if let Some((start, end)) = self.pc {
+			table.insert("pc".to_string(), numbers(vec![start as i64, end as i64]));
+		}
+		table.insert("classes".to_string(), toml::Value::Array(
+			self.classes.iter().map(|c| toml::Value::String(c.name().to_string())).collect()));
+		table.insert("ports".to_string(), numbers(self.ports.iter().map(|&p| p as i64).collect()));
+		table.insert("instructions".to_string(), toml::Value::Boolean(self.instructions));
+		table.insert("interrupts".to_string(), toml::Value::Boolean(self.interrupts));
+		if let Some(n) = self.ring {
+			table.insert("ring".to_string(), toml::Value::Integer(n as i64));
+		}
+		table.insert("breakpoints".to_string(), numbers(self.breakpoints.iter().map(|&pc| pc as i64).collect()));
+		table
+	}
+
+	fn matches(&self, pc: u16, opcode: u8, arg1: u8) -> bool {
+		if !self.instructions {
+			return false;
+		}
+		if let Some((start, end)) = self.pc {
+			if pc < start || pc > end {
+				return false;
+			}
+		}
+		let class = OpClass::of(opcode);
+		if !self.classes.is_empty() && !self.classes.contains(&class) {
+			return false;
+		}
+		self.ports.is_empty() || (class == OpClass::Io && self.ports.contains(&arg1))
+	}
+}
+
+
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+	pub cycle: u64,
+	// interrupt number for interrupt entries
+	pub interrupt: Option<u8>,
+	pub pc: u16,
+	pub opcode: u8,
+	pub arg1: u8,
+	pub arg2: u8,
+	// registers afterwards: A, PSW, B, C, D, E, H, L
+	pub regs: [u8; 8],
+	pub next_pc: u16,
+	pub sp: u16
+}
+
+impl TraceEntry {
+	fn new(cycle: u64, interrupt: Option<u8>, pc: u16, opcode: u8, arg1: u8, arg2: u8, cpu: &CPU) -> TraceEntry {
+		TraceEntry {
+			cycle,
+			interrupt,
+			pc,
+			opcode,
+			arg1,
+			arg2,
+			regs: [cpu.a, cpu.cc.get_psw(), cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l],
+			next_pc: cpu.pc,
+			sp: cpu.sp
+		}
+	}
+
+	// "<cycle> <pc> <instruction> | AF BC DE HL PC SP", the same register layout as `CPU::print_state`
+	fn write_text<W: Write>(&self, out: &mut W) -> Result<()> {
+		let text = match self.interrupt {
+			Some(n) => Cow::Owned(format!("INT    {}", n)),
+			None => disassemble(self.opcode, self.arg1, self.arg2).0
+		};
+		let r = &self.regs;
+		writeln!(out, "{:10} {:04X} {:<16}| {:02X}{:02X} {:02X}{:02X} {:02X}{:02X} {:02X}{:02X} {:04X} {:04X}",
+			self.cycle, self.pc, text, r[0], r[1], r[2], r[3], r[4], r[5], r[6], r[7], self.next_pc, self.sp)
+	}
+
+	// cycle (u64), kind (0 instruction, 1 interrupt), opcode or interrupt number, arg1, arg2,
+	// pc, next pc, sp (u16), A, PSW, B, C, D, E, H, L, all little endian
+	fn write_binary<W: Write>(&self, out: &mut W) -> Result<()> {
+		let mut record = [0u8; RECORD_SIZE];
+		for i in 0..8 {
+			record[i] = (self.cycle >> (i * 8)) as u8;
+		}
+		match self.interrupt {
+			Some(n) => { record[8] = 1; record[9] = n; },
+			None => { record[8] = 0; record[9] = self.opcode; }
+		}
+		record[10] = self.arg1;
+		record[11] = self.arg2;
+		for (i, &word) in [self.pc, self.next_pc, self.sp].iter().enumerate() {
+			record[12 + i * 2] = word as u8;
+			record[13 + i * 2] = (word >> 8) as u8;
+		}
+		record[18..26].copy_from_slice(&self.regs);
+		out.write_all(&record)
+	}
+}
+
+
+// Writes trace entries to a file, directly or from a ring buffer of the last entries.
+#[derive(Debug)]
+pub struct Tracer {
+	config: TraceConfig,
+	out: BufWriter<File>,
+	ring: VecDeque<TraceEntry>,
+	failed: bool
+}
+
+impl Tracer {
+	pub fn create(config: &TraceConfig) -> Result<Tracer> {
+		let mut out = BufWriter::new(File::create(&config.file)?);
+		if config.format == TraceFormat::Binary {
+			out.write_all(MAGIC)?;
+		}
+		Ok(Tracer {
+			config: config.clone(),
+			out,
+			ring: VecDeque::with_capacity(config.ring.unwrap_or(0)),
+			failed: false
+		})
+	}
+
+	pub fn breakpoints(&self) -> &[u16] {
+		&self.config.breakpoints
+	}
+
+	pub fn instruction(&mut self, cycle: u64, pc: u16, opcode: u8, arg1: u8, arg2: u8, cpu: &CPU) {
+		if self.config.matches(pc, opcode, arg1) {
+			self.record(TraceEntry::new(cycle, None, pc, opcode, arg1, arg2, cpu));
+		}
+	}
+
+	pub fn interrupt(&mut self, cycle: u64, pc: u16, num: u8, cpu: &CPU) {
+		if self.config.interrupts {
+			self.record(TraceEntry::new(cycle, Some(num), pc, 0, 0, 0, cpu));
+		}
+	}
+
+	fn record(&mut self, entry: TraceEntry) {
+		match self.config.ring {
+			Some(size) => {
+				if self.ring.len() == size {
+					self.ring.pop_front();
+				}
+				self.ring.push_back(entry);
+			},
+			None => self.write(&entry)
+		}
+	}
+
+	fn write(&mut self, entry: &TraceEntry) {
+		let result = match self.config.format {
+			TraceFormat::Text => entry.write_text(&mut self.out),
+			TraceFormat::Binary => entry.write_binary(&mut self.out)
+		};
+		if let Err(e) = result {
+			if !self.failed {
+				println!("=> Can't write trace to {}: {}", self.config.file, e);
+				self.failed = true;
+			}
+		}
+	}
+
+	// writes out the ring buffer, e.g. on a breakpoint
+	pub fn dump(&mut self) {
+		let entries: Vec<TraceEntry> = self.ring.drain(..).collect();
+		for entry in &entries {
+			self.write(entry);
+		}
+		let _ = self.out.flush();
+		if self.config.ring.is_some() {
+			println!("=> Last {} instructions written to {}", entries.len(), self.config.file);
+		}
+	}
+}
+
+impl Drop for Tracer {
+	fn drop(&mut self) {
+		// the last instructions before a crash are the interesting ones
+		if thread::panicking() {
+			self.dump();
+		}
+		let _ = self.out.flush();
+	}
+}
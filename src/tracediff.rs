@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::*;
+
+use machine::Machine;
+use disassemble::disassemble;
+
+// instructions shown before the mismatch
+const CONTEXT: usize = 8;
+const REGISTERS: [&str; 6] = ["AF", "BC", "DE", "HL", "PC", "SP"];
+// bit in the PSW and the letter print_state uses
+const FLAGS: [(u8, char); 5] = [(6, 'z'), (7, 's'), (2, 'p'), (0, 'c'), (4, 'a')];
+
+// The registers after one instruction of the reference, and the memory it wrote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefState {
+	pub line: usize,
+	pub regs: [u16; 6],
+	pub writes: Vec<(u16, u8)>
+}
+
+// The last six 4 digit hex words of a line are AF BC DE HL PC SP, like print_state prints them,
+// so a leading address or disassembly and the trailing flags are skipped.
+pub fn parse_state(line: &str) -> Option<[u16; 6]> {
+	let words: Vec<u16> = line.split(|c: char| c.is_whitespace() || c == '|')
+		.filter(|w| w.len() == 4)
+		.filter_map(|w| u16::from_str_radix(w, 16).ok())
+		.collect();
+	if words.len() < 6 {
+		return None;
+	}
+	let w = &words[words.len() - 6..];
+	Some([w[0], w[1], w[2], w[3], w[4], w[5]])
+}
+
+// Reads a reference log. Besides the state lines, `W <addr> <value>` lines (hex) list the memory
+// written by the instruction before. Empty lines and lines starting with `;` are ignored.
+pub fn read_reference(filename: &str) -> Result<Vec<RefState>> {
+	let file = File::open(filename)?;
+	let mut states: Vec<RefState> = Vec::new();
+
+	for (n, line) in BufReader::new(file).lines().enumerate() {
+		let line = line?;
+		let trimmed = line.trim();
+		if trimmed.is_empty() || trimmed.starts_with(';') {
+			continue;
+		}
+		if trimmed.starts_with("W ") {
+			let parts: Vec<&str> = trimmed.split_whitespace().collect();
+			let write = if parts.len() == 3 {
+				match (u16::from_str_radix(parts[1], 16), u8::from_str_radix(parts[2], 16)) {
+					(Ok(addr), Ok(value)) => Some((addr, value)),
+					_ => None
+				}
+			} else { None };
+			match (write, states.last_mut()) {
+				(Some(write), Some(state)) => state.writes.push(write),
+				_ => println!("=> Ignoring invalid write in line {}: {}", n + 1, trimmed)
+			}
+			continue;
+		}
+		match parse_state(trimmed) {
+			Some(regs) => states.push(RefState {line: n + 1, regs, writes: Vec::new()}),
+			None => println!("=> Ignoring line {}: {}", n + 1, trimmed)
+		}
+	}
+	Ok(states)
+}
+
+fn format_regs(regs: &[u16; 6]) -> String {
+	regs.iter().map(|r| format!("{:04X}", r)).collect::<Vec<_>>().join(" ")
+}
+
+
+#[derive(Debug)]
+pub struct Mismatch {
+	// number of the instruction, counted from 1
+	pub instruction: usize,
+	// line in the reference log
+	pub line: usize,
+	pub reason: String,
+	pub context: Vec<String>
+}
+
+impl fmt::Display for Mismatch {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "=> Mismatch at instruction {} (reference line {}): {}", self.instruction, self.line, self.reason)?;
+		writeln!(f, "  {:>8} {:4} {:<16}| AF   BC   DE   HL   PC   SP", "#", "PC", "instruction")?;
+		for line in &self.context {
+			writeln!(f, "{}", line)?;
+		}
+		Ok(())
+	}
+}
+
+// Runs the machine one instruction per reference state and stops at the first difference.
+// Returns the number of matching instructions when the whole reference matches.
+// If the reference lists writes, any other write to memory counts as a difference too.
+pub fn compare(m: &mut Machine, reference: &[RefState]) -> ::std::result::Result<usize, Mismatch> {
+	m.log_accesses(true);
+	let result = compare_steps(m, reference);
+	m.log_accesses(false);
+	result
+}
+
+fn compare_steps(m: &mut Machine, reference: &[RefState]) -> ::std::result::Result<usize, Mismatch> {
+	let mut context: VecDeque<String> = VecDeque::with_capacity(CONTEXT);
+	let check_writes = reference.iter().any(|s| !s.writes.is_empty());
+
+	for (i, expected) in reference.iter().enumerate() {
+		let pc = m.cpu().pc;
+		let (text, _) = {
+			let mem = m.memory();
+			let p = pc as usize;
+			disassemble(mem[p], mem[(p + 1) & 0xFFFF], mem[(p + 2) & 0xFFFF])
+		};
+		let line = |regs: &[u16; 6]| format!("{:8} {:04X} {:<16}| {}", i + 1, pc, text, format_regs(regs));
+
+		let halted = m.is_halted();
+		m.step();
+		let regs = m.cpu().registers();
+		let mut reason = None;
+
+		if halted {
+			reason = Some("CPU halted".to_string());
+		}
+		for r in 0..6 {
+			if reason.is_some() || regs[r] == expected.regs[r] {
+				continue;
+			}
+			let flags: Vec<String> = FLAGS.iter()
+				.filter(|&&(bit, _)| (regs[0] >> bit) & 1 != (expected.regs[0] >> bit) & 1)
+				.map(|&(bit, name)| format!("{} is {}, expected {}", name, (regs[0] >> bit) & 1, (expected.regs[0] >> bit) & 1))
+				.collect();
+			// only the unused PSW bits may differ, then the raw values tell more
+			reason = Some(if r == 0 && regs[0] >> 8 == expected.regs[0] >> 8 && !flags.is_empty() {
+				format!("flags differ: {}", flags.join(", "))
+			}
+			else {
+				format!("{} is {:04X}, expected {:04X}", REGISTERS[r], regs[r], expected.regs[r])
+			});
+		}
+		for &(addr, value) in &expected.writes {
+			let actual = m.memory()[addr as usize];
+			if reason.is_none() && actual != value {
+				reason = Some(format!("memory at {:04X} is {:02X}, expected write of {:02X}", addr, actual, value));
+			}
+		}
+		if check_writes && reason.is_none() {
+			let stray = m.accesses().iter()
+				.filter(|a| a.is_write() && !a.is_io())
+				.find(|a| !expected.writes.contains(&(a.addr, a.value)));
+			if let Some(a) = stray {
+				reason = Some(format!("wrote {:02X} to {:04X}, not in the reference", a.value, a.addr));
+			}
+		}
+
+		match reason {
+			Some(reason) => {
+				let mut context: Vec<String> = context.into_iter().collect();
+				context.push(format!("> {}", line(&regs)));
+				context.push(format!("  {:8} {:4} {:16}| {}  (reference)", "", "", "", format_regs(&expected.regs)));
+				return Err(Mismatch {instruction: i + 1, line: expected.line, reason, context});
+			},
+			None => {
+				if context.len() == CONTEXT {
+					context.pop_front();
+				}
+				context.push_back(format!("  {}", line(&regs)));
+			}
+		}
+	}
+	Ok(reference.len())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// MVI M,55 with HL 0000, expected to write the given memory
+	fn run(writes: Vec<(u16, u8)>, af: u16) -> ::std::result::Result<usize, Mismatch> {
+		let mut m = Machine::new();
+		m.load_program(&[0x36, 0x55], 0).unwrap();
+		let mut regs = m.cpu().registers();
+		regs[0] ^= af;
+		regs[4] = 0x0002;
+		compare(&mut m, &[RefState {line: 1, regs, writes}])
+	}
+
+	#[test]
+	fn writes() {
+		assert_eq!(run(vec![(0x0000, 0x55)], 0).unwrap(), 1);
+		// references without writes don't check them
+		assert_eq!(run(vec![], 0).unwrap(), 1);
+		assert_eq!(run(vec![(0x0000, 0x56)], 0).unwrap_err().reason, "memory at 0000 is 55, expected write of 56");
+		assert_eq!(run(vec![(0x2000, 0x00)], 0).unwrap_err().reason, "wrote 55 to 0000, not in the reference");
+	}
+
+	#[test]
+	fn flags() {
+		assert_eq!(run(vec![], 0x0041).unwrap_err().reason, "flags differ: z is 0, expected 1, c is 0, expected 1");
+		// only unused bits
+		let af = Machine::new().cpu().registers()[0];
+		assert_eq!(run(vec![], 0x0008).unwrap_err().reason, format!("AF is {:04X}, expected {:04X}", af, af ^ 0x0008));
+	}
+}
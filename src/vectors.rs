@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde_json;
+
+use cpu::CPU;
+use alu::ALU;
+use operation::execute;
+use disassemble::instr_len;
+use bus::{Access, AccessKind, LoggingBus, log_fetch};
+
+// Single step test vectors like the community test suites publish them, one JSON array per file:
+// {"name": "..", "initial": {..}, "final": {..}, "cycles": [[addr, value, "flags"], ..], "ports": [[port, value, "r"], ..]}
+#[derive(Debug, Deserialize)]
+pub struct Vector {
+	pub name: String,
+	pub initial: State,
+	#[serde(rename = "final")]
+	pub expected: State,
+	// one entry per clock cycle
+	#[serde(default)]
+	pub cycles: Vec<Cycle>,
+	// the value IN reads ("r") or OUT writes ("w")
+	#[serde(default)]
+	pub ports: Vec<(u8, u8, String)>
+}
+
+// Address and data on the bus during one clock cycle, null when nothing is driven, and what happens:
+// the flags contain r for a read, w for a write and i if it is an I/O port instead of memory.
+pub type Cycle = (Option<u16>, Option<u8>, String);
+
+#[derive(Debug, Deserialize)]
+pub struct State {
+	pub pc: u16,
+	pub sp: u16,
+	pub a: u8,
+	pub b: u8,
+	pub c: u8,
+	pub d: u8,
+	pub e: u8,
+	pub f: u8,
+	pub h: u8,
+	pub l: u8,
+	pub ram: Vec<(u16, u8)>
+}
+
+impl Vector {
+	// None if the initial RAM doesn't hold the byte at PC
+	pub fn opcode(&self) -> Option<u8> {
+		self.initial.ram.iter()
+			.find(|&&(addr, _)| addr == self.initial.pc)
+			.map(|&(_, value)| value)
+	}
+
+	// Runs the instruction once and returns the first difference to the final state.
+	pub fn run(&self, alu: &mut ALU) -> Result<(), String> {
+		if self.opcode().is_none() {
+			return Err(format!("no instruction at PC {:04X}", self.initial.pc));
+		}
+		let s = &self.initial;
+		let mut cpu = CPU::new();
+		cpu.a = s.a;
+		cpu.b = s.b;
+		cpu.c = s.c;
+		cpu.d = s.d;
+		cpu.e = s.e;
+		cpu.h = s.h;
+		cpu.l = s.l;
+		cpu.sp = s.sp;
+		cpu.pc = s.pc;
+		cpu.cc.set_psw(s.f);
+		let mut mem = vec![0; 0x10000];
+		for &(addr, value) in &s.ram {
+			mem[addr as usize] = value;
+		}
+
+		let pc = s.pc as usize;
+		let (opcode, arg1, arg2) = (mem[pc], mem[(pc + 1) & 0xFFFF], mem[(pc + 2) & 0xFFFF]);
+		// the bus traffic, like Machine logs it
+		let mut log = Vec::new();
+		log_fetch(&mut log, s.pc, [opcode, arg1, arg2], instr_len(opcode));
+		let cycles = execute(&mut cpu, alu, &mut LoggingBus::new(&mut mem, &mut log), opcode, arg1, arg2);
+		// ports are the machine's job, execute only skips them
+		if opcode == 0xDB {
+			if let Some(&(port, value, _)) = self.ports.iter().find(|p| p.2 == "r") {
+				if port != arg1 {
+					return Err(format!("IN read port {:02X}, expected {:02X}", arg1, port));
+				}
+				cpu.a = value;
+			}
+			log.push(Access {addr: arg1 as u16, value: cpu.a, kind: AccessKind::IoRead});
+		}
+		if opcode == 0xD3 {
+			for &(port, value, ref dir) in &self.ports {
+				if dir == "w" && (port, value) != (arg1, cpu.a) {
+					return Err(format!("OUT wrote {:02X} to port {:02X}, expected {:02X} to {:02X}", cpu.a, arg1, value, port));
+				}
+			}
+			log.push(Access {addr: arg1 as u16, value: cpu.a, kind: AccessKind::IoWrite});
+		}
+
+		let e = &self.expected;
+		let regs = [("A", cpu.a, e.a), ("B", cpu.b, e.b), ("C", cpu.c, e.c), ("D", cpu.d, e.d),
+			("E", cpu.e, e.e), ("H", cpu.h, e.h), ("L", cpu.l, e.l), ("F", cpu.cc.get_psw(), e.f)];
+		for &(name, actual, expected) in regs.iter() {
+			if actual != expected {
+				return Err(format!("{} is {:02X}, expected {:02X}", name, actual, expected));
+			}
+		}
+		if cpu.sp != e.sp {
+			return Err(format!("SP is {:04X}, expected {:04X}", cpu.sp, e.sp));
+		}
+		if cpu.pc != e.pc {
+			return Err(format!("PC is {:04X}, expected {:04X}", cpu.pc, e.pc));
+		}
+		for &(addr, value) in &e.ram {
+			if mem[addr as usize] != value {
+				return Err(format!("memory at {:04X} is {:02X}, expected {:02X}", addr, mem[addr as usize], value));
+			}
+		}
+		if !self.cycles.is_empty() {
+			let expected: Vec<Access> = self.cycles.iter().filter_map(cycle_access).collect();
+			for i in 0..log.len().max(expected.len()) {
+				if log.get(i) != expected.get(i) {
+					return Err(format!("bus access {} is {}, expected {}", i + 1, describe(log.get(i)), describe(expected.get(i))));
+				}
+			}
+		}
+		// HLT returns 0 to stop the machine
+		if cycles != 0 && !self.cycles.is_empty() && cycles as usize != self.cycles.len() {
+			return Err(format!("took {} cycles, expected {}", cycles, self.cycles.len()));
+		}
+		Ok(())
+	}
+}
+
+// the transfer of a cycle, if there is one
+fn cycle_access(&(addr, value, ref flags): &Cycle) -> Option<Access> {
+	let (addr, value) = match (addr, value) {
+		(Some(addr), Some(value)) => (addr, value),
+		_ => return None
+	};
+	let io = flags.contains('i');
+	let kind = match (flags.contains('r'), flags.contains('w'), io) {
+		(true, _, false) => AccessKind::Read,
+		(true, _, true) => AccessKind::IoRead,
+		(false, true, false) => AccessKind::Write,
+		(false, true, true) => AccessKind::IoWrite,
+		_ => return None
+	};
+	// the port is on both halves of the address bus
+	Some(Access {addr: if io { addr & 0xFF } else { addr }, value, kind})
+}
+
+fn describe(access: Option<&Access>) -> String {
+	match access {
+		Some(a) => format!("{:?} of {:02X} at {:04X}", a.kind, a.value, a.addr),
+		None => "none".to_string()
+	}
+}
+
+pub fn parse_vectors(json: &str) -> serde_json::Result<Vec<Vector>> {
+	serde_json::from_str(json)
+}
+
+// Reads a vector file, or every .json file of a directory.
+pub fn read_vectors(path: &str) -> io::Result<Vec<Vector>> {
+	let path = Path::new(path);
+	let mut files = Vec::new();
+	if path.is_dir() {
+		for entry in fs::read_dir(path)? {
+			let file = entry?.path();
+			if file.extension().map_or(false, |e| e == "json") {
+				files.push(file);
+			}
+		}
+		files.sort();
+	}
+	else {
+		files.push(path.to_path_buf());
+	}
+
+	let mut vectors = Vec::new();
+	for file in files {
+		let json = fs::read_to_string(&file)?;
+		let parsed = parse_vectors(&json)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file.display(), e)))?;
+		vectors.extend(parsed);
+	}
+	Ok(vectors)
+}
+
+
+// Passed and run vectors of one opcode, with the first failure.
+#[derive(Debug, Default)]
+pub struct OpcodeResult {
+	pub passed: usize,
+	pub total: usize,
+	pub failure: Option<(String, String)>
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+	pub opcodes: BTreeMap<u8, OpcodeResult>,
+	// names of the vectors that couldn't be run
+	pub malformed: Vec<String>
+}
+
+pub fn run_all(vectors: &[Vector]) -> Report {
+	let mut alu = ALU::new();
+	let mut report = Report::default();
+	for v in vectors {
+		let opcode = match v.opcode() {
+			Some(opcode) => opcode,
+			None => {
+				report.malformed.push(v.name.clone());
+				continue;
+			}
+		};
+		let result = report.opcodes.entry(opcode).or_insert_with(OpcodeResult::default);
+		result.total += 1;
+		match v.run(&mut alu) {
+			Ok(_) => result.passed += 1,
+			Err(reason) => if result.failure.is_none() {
+				result.failure = Some((v.name.clone(), reason));
+			}
+		}
+	}
+	report
+}
+
+pub fn print_report(report: &Report) {
+	let results = &report.opcodes;
+	let (mut passed, mut total) = (0, 0);
+	for (opcode, r) in results {
+		passed += r.passed;
+		total += r.total;
+		match r.failure {
+			Some((ref name, ref reason)) =>
+				println!("=> {:02X}: {:5}/{:<5} {:5.1}%  first failure \"{}\": {}", opcode, r.passed, r.total,
+					100.0 * r.passed as f64 / r.total as f64, name, reason),
+			None => println!("=> {:02X}: {:5}/{:<5} 100.0%", opcode, r.passed, r.total)
+		}
+	}
+	let full = results.values().filter(|r| r.passed == r.total).count();
+	println!("=> {} of {} vectors pass, {} of {} opcodes completely", passed, total, full, results.len());
+	if !report.malformed.is_empty() {
+		println!("=> {} vectors without an instruction at PC, e.g. \"{}\"", report.malformed.len(), report.malformed[0]);
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const VECTORS: &str = r#"[
+		{"name": "3c 0000", "initial": {"pc": 256, "sp": 1024, "a": 15, "b": 0, "c": 0, "d": 0, "e": 0, "f": 3, "h": 0, "l": 0, "ram": [[256, 60]]},
+			"final": {"pc": 257, "sp": 1024, "a": 16, "b": 0, "c": 0, "d": 0, "e": 0, "f": 19, "h": 0, "l": 0, "ram": [[256, 60]]},
+			"cycles": [[256, 60, "r"], [257, null, "-"], [257, null, "-"], [257, null, "-"], [257, null, "-"]]},
+		{"name": "c5 0000", "initial": {"pc": 0, "sp": 0, "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": [[0, 197]]},
+			"final": {"pc": 1, "sp": 65534, "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": [[65535, 18], [65534, 52]]},
+			"cycles": [[0, 197, "r-m"], [null, null, "---"], [null, null, "---"], [null, null, "---"], [null, null, "---"],
+				[null, null, "---"], [null, null, "---"], [65535, 18, "-wm"], [null, null, "---"], [null, null, "---"],
+				[65534, 52, "-wm"]]},
+		{"name": "c5 0001", "initial": {"pc": 0, "sp": 0, "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": [[0, 197]]},
+			"final": {"pc": 1, "sp": 65534, "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": [[65535, 52], [65534, 18]]}},
+		{"name": "db 0000", "initial": {"pc": 16, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": [[16, 219], [17, 1]]},
+			"final": {"pc": 18, "sp": 0, "a": 153, "b": 0, "c": 0, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": []},
+			"ports": [[1, 153, "r"]]},
+		{"name": "d3 0000", "initial": {"pc": 16, "sp": 0, "a": 7, "b": 0, "c": 0, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": [[16, 211], [17, 3]]},
+			"final": {"pc": 18, "sp": 0, "a": 7, "b": 0, "c": 0, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": []},
+			"cycles": [[16, 211, "r-m"], [17, 3, "r-m"], [null, null, "---"], [null, null, "---"], [null, null, "---"],
+				[null, null, "---"], [null, null, "---"], [null, null, "---"], [null, null, "---"], [771, 7, "-wi"]],
+			"ports": [[3, 7, "w"]]},
+		{"name": "no opcode", "initial": {"pc": 16, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": []},
+			"final": {"pc": 17, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0, "ram": []}}
+	]"#;
+
+	#[test]
+	fn parse_and_run() {
+		let vectors = parse_vectors(VECTORS).unwrap();
+		assert_eq!(vectors.len(), 6);
+		assert_eq!(vectors[0].opcode(), Some(0x3C));
+		assert_eq!(vectors[0].cycles[1], (Some(257), None, "-".to_string()));
+		assert_eq!(vectors[3].ports, vec![(1, 153, "r".to_string())]);
+		assert_eq!(vectors[5].opcode(), None);
+
+		let report = run_all(&vectors);
+		let results = &report.opcodes;
+		assert_eq!(results.keys().cloned().collect::<Vec<u8>>(), vec![0x3C, 0xC5, 0xD3, 0xDB]);
+		assert_eq!((results[&0x3C].passed, results[&0x3C].total), (1, 1));
+		assert_eq!((results[&0xD3].passed, results[&0xD3].total), (1, 1));
+		assert_eq!((results[&0xDB].passed, results[&0xDB].total), (1, 1));
+		assert_eq!(report.malformed, vec!["no opcode".to_string()]);
+
+		// the second PUSH expects the bytes the wrong way round
+		let push = &results[&0xC5];
+		assert_eq!((push.passed, push.total), (1, 2));
+		assert_eq!(push.failure, Some(("c5 0001".to_string(), "memory at FFFF is 12, expected 34".to_string())));
+	}
+
+	#[test]
+	fn cycle_count() {
+		let mut vectors = parse_vectors(VECTORS).unwrap();
+		vectors[0].cycles.pop();
+		assert_eq!(vectors[0].run(&mut ALU::new()), Err("took 5 cycles, expected 4".to_string()));
+	}
+
+	#[test]
+	fn bus_traffic() {
+		let mut vectors = parse_vectors(VECTORS).unwrap();
+		assert_eq!(vectors[1].run(&mut ALU::new()), Ok(()));
+		vectors[1].cycles.swap(7, 10);
+		assert_eq!(vectors[1].run(&mut ALU::new()),
+			Err("bus access 2 is Write of 12 at FFFF, expected Write of 34 at FFFE".to_string()));
+
+		vectors[4].cycles[9].0 = Some(0x0404);
+		assert_eq!(vectors[4].run(&mut ALU::new()),
+			Err("bus access 3 is IoWrite of 07 at 0003, expected IoWrite of 07 at 0004".to_string()));
+		vectors[4].cycles.pop();
+		assert_eq!(vectors[4].run(&mut ALU::new()),
+			Err("bus access 3 is IoWrite of 07 at 0003, expected none".to_string()));
+	}
+
+	#[test]
+	fn ports() {
+		let mut vectors = parse_vectors(VECTORS).unwrap();
+		vectors[4].ports[0].1 = 8;
+		assert_eq!(vectors[4].run(&mut ALU::new()), Err("OUT wrote 07 to port 03, expected 08 to 03".to_string()));
+		vectors[3].ports[0].0 = 2;
+		assert_eq!(vectors[3].run(&mut ALU::new()), Err("IN read port 01, expected 02".to_string()));
+	}
+}
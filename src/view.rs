@@ -0,0 +1,116 @@
+use toml;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scaling {
+	// largest size that fits the window
+	Fit,
+	// largest whole multiple that fits the window
+	Integer,
+	// fixed factor
+	Factor(f64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewConfig {
+	// clockwise, in degrees: 0, 90, 180 or 270
+	pub rotation: u32,
+	pub scaling: Scaling,
+	pub fullscreen: bool
+}
+
+impl ViewConfig {
+	pub fn new() -> ViewConfig {
+		ViewConfig {
+			rotation: 0,
+			scaling: Scaling::Fit,
+			fullscreen: false
+		}
+	}
+
+	// `scale` is "fit", "integer" or a number
+	pub fn from_table(table: &toml::value::Table) -> ViewConfig {
+		let mut config = ViewConfig::new();
+
+		if let Some(rotation) = table.get("rotation").and_then(|v| v.as_integer()) {
+			if rotation % 90 == 0 {
+				config.rotation = (rotation % 360 + 360) as u32 % 360;
+			}
+			else {
+				println!("=> Ignoring invalid rotation: {}", rotation);
+			}
+		}
+		match table.get("scale") {
+			Some(&toml::Value::String(ref s)) if s == "fit" => config.scaling = Scaling::Fit,
+			Some(&toml::Value::String(ref s)) if s == "integer" => config.scaling = Scaling::Integer,
+			Some(&toml::Value::Float(f)) if f > 0.0 => config.scaling = Scaling::Factor(f),
+			Some(&toml::Value::Integer(i)) if i > 0 => config.scaling = Scaling::Factor(i as f64),
+			Some(other) => println!("=> Ignoring invalid scale: {}", other),
+			None => ()
+		}
+		if let Some(fullscreen) = table.get("fullscreen").and_then(|v| v.as_bool()) {
+			config.fullscreen = fullscreen;
+		}
+		config
+	}
+
+	pub fn to_table(&self) -> toml::value::Table {
+		let mut table = toml::value::Table::new();
+		table.insert("rotation".to_string(), toml::Value::Integer(self.rotation as i64));
+		table.insert("scale".to_string(), match self.scaling {
+			Scaling::Fit => toml::Value::String("fit".to_string()),
+			Scaling::Integer => toml::Value::String("integer".to_string()),
+			Scaling::Factor(f) => toml::Value::Float(f)
+		});
+		table.insert("fullscreen".to_string(), toml::Value::Boolean(self.fullscreen));
+		table
+	}
+
+	pub fn rotate(&mut self) {
+		self.rotation = (self.rotation + 90) % 360;
+	}
+
+	// size of the image after rotation
+	pub fn rotated_size(&self, (w, h): (u32, u32)) -> (u32, u32) {
+		if self.rotation % 180 == 0 { (w, h) } else { (h, w) }
+	}
+
+	// initial window size for an image of the given size
+	pub fn window_size(&self, size: (u32, u32), default_scale: f64) -> [u32; 2] {
+		let (w, h) = self.rotated_size(size);
+		let scale = match self.scaling {
+			Scaling::Factor(f) => f,
+			_ => default_scale
+		};
+		[(w as f64 * scale) as u32, (h as f64 * scale) as u32]
+	}
+
+	pub fn scale(&self, size: (u32, u32), window: (f64, f64)) -> f64 {
+		let (w, h) = self.rotated_size(size);
+		let fit = (window.0 / w as f64).min(window.1 / h as f64);
+		match self.scaling {
+			Scaling::Fit => fit,
+			Scaling::Integer => fit.floor().max(1.0),
+			Scaling::Factor(f) => f
+		}
+	}
+
+	// Maps image pixels (origin top left) to normalized device coordinates,
+	// rotated, scaled and centred in the window with black bars around it.
+	pub fn transform(&self, size: (u32, u32), window: (f64, f64)) -> [[f64; 3]; 2] {
+		let s = self.scale(size, window);
+		let (w, h) = (size.0 as f64, size.1 as f64);
+		let (sin, cos) = match self.rotation {
+			90 => (1.0, 0.0),
+			180 => (0.0, -1.0),
+			270 => (-1.0, 0.0),
+			_ => (0.0, 1.0)
+		};
+		let kx = 2.0 * s / window.0;
+		let ky = 2.0 * s / window.1;
+
+		[
+			[kx * cos, -kx * sin, kx * (sin * h - cos * w) / 2.0],
+			[-ky * sin, -ky * cos, ky * (sin * w + cos * h) / 2.0]
+		]
+	}
+}